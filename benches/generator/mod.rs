@@ -0,0 +1,67 @@
+//! Generate synthetic expositions for benchmarks, the output only depends on the arguments.
+#![allow(dead_code)]
+use std::fmt::Write;
+
+/// `families` counters each having `series` series with `labels` labels
+pub fn series(families: usize, series: usize, labels: usize) -> String {
+    let mut res = String::new();
+    for f in 0..families {
+        let _ = writeln!(res, "# HELP family_{}_total Generated family {}", f, f);
+        let _ = writeln!(res, "# TYPE family_{}_total counter", f);
+        for s in 0..series {
+            let _ = write!(res, "family_{}_total{{", f);
+            for l in 0..labels {
+                if l > 0 {
+                    res.push(',');
+                }
+                // Only the first label changes between series, the other ones look like target labels
+                let value = if l == 0 { s } else { l };
+                let _ = write!(res, "label_{}=\"value_{}\"", l, value);
+            }
+            let _ = writeln!(res, "}} {} 1600000000000", s * 7 + f);
+        }
+    }
+    res
+}
+
+/// `count` series with many long labels, like the ones of a kube-state-metrics pod
+pub fn label_heavy(count: usize) -> String {
+    let mut res = String::from("# TYPE kube_pod_info gauge\n");
+    for s in 0..count {
+        let _ = writeln!(
+            res,
+            "kube_pod_info{{namespace=\"namespace-{}\",pod=\"deployment-{}-7d9f8c6b5d-x{:04}\",uid=\"{:08x}-1f2e-4d3c-8b7a-{:012x}\",host_ip=\"10.0.{}.{}\",pod_ip=\"172.16.{}.{}\",node=\"ip-10-0-{}-{}.eu-west-1.compute.internal\",created_by_kind=\"ReplicaSet\",created_by_name=\"deployment-{}-7d9f8c6b5d\",priority_class=\"\",host_network=\"false\"}} 1",
+            s % 20,
+            s % 100,
+            s,
+            s,
+            s,
+            s / 256 % 256,
+            s % 256,
+            s / 256 % 256,
+            s % 256,
+            s / 256 % 256,
+            s % 256,
+            s % 100
+        );
+    }
+    res
+}
+
+/// `count` series whose label values and docstrings need escaping
+pub fn escape_heavy(count: usize) -> String {
+    let mut res = String::new();
+    for s in 0..count {
+        let _ = writeln!(
+            res,
+            "# HELP escaped_{} A docstring with a \\\\ backslash\\nand a line break",
+            s
+        );
+        let _ = writeln!(
+            res,
+            "escaped_{}{{path=\"C:\\\\Program Files\\\\{}\",query=\"say \\\"hi\\\"\\n{}\"}} {}",
+            s, s, s, s
+        );
+    }
+    res
+}
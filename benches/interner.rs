@@ -0,0 +1,19 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use prometheus_exposition_format_rs::interner::Parser;
+use prometheus_exposition_format_rs::parse_complete;
+use std::fs;
+
+fn bench_interner(c: &mut Criterion) {
+    let input = fs::read_to_string("fixtures/ok_geth.prom").unwrap();
+    let mut group = c.benchmark_group("geth");
+    group.bench_function("parse_complete", |b| {
+        b.iter(|| parse_complete(&input).unwrap())
+    });
+    // The parser is reused across iterations like it would be across scrapes of the same target
+    let mut parser = Parser::default();
+    group.bench_function("interned", |b| b.iter(|| parser.parse(&input).unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, bench_interner);
+criterion_main!(benches);
@@ -0,0 +1,39 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use prometheus_exposition_format_rs::parse_complete;
+use std::fs;
+
+mod generator;
+
+fn bench_inputs(c: &mut Criterion) {
+    let inputs = [
+        ("geth", fs::read_to_string("fixtures/ok_geth.prom").unwrap()),
+        ("labels", generator::label_heavy(10_000)),
+        ("escapes", generator::escape_heavy(10_000)),
+    ];
+    let mut group = c.benchmark_group("parse_complete");
+    for (name, input) in inputs.iter() {
+        group.throughput(Throughput::Bytes(input.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), input, |b, input| {
+            b.iter(|| parse_complete(input).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn bench_million_series(c: &mut Criterion) {
+    let input = generator::series(1_000, 1_000, 3);
+    let mut group = c.benchmark_group("parse_complete");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(1_000_000));
+    group.bench_function("1M_series", |b| b.iter(|| parse_complete(&input).unwrap()));
+    #[cfg(feature = "rayon")]
+    group.bench_function("1M_series_parallel", |b| {
+        b.iter(|| {
+            prometheus_exposition_format_rs::parallel::parse_complete_parallel(&input).unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_inputs, bench_million_series);
+criterion_main!(benches);
@@ -0,0 +1,23 @@
+//! Write a synthetic exposition to stdout, for example to profile the parser outside of the benchmarks:
+//! `cargo run --release --example generate_fixture -- series 1000 1000 3 > /tmp/1M.prom`
+use std::env;
+use std::io::{self, Write};
+
+#[path = "../benches/generator/mod.rs"]
+mod generator;
+
+fn main() -> io::Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let num =
+        |i: usize, default: usize| args.get(i).and_then(|a| a.parse().ok()).unwrap_or(default);
+    let output = match args.first().map(String::as_str) {
+        Some("series") => generator::series(num(1, 1_000), num(2, 1_000), num(3, 3)),
+        Some("labels") => generator::label_heavy(num(1, 10_000)),
+        Some("escapes") => generator::escape_heavy(num(1, 10_000)),
+        _ => {
+            eprintln!("usage: generate_fixture series [families] [series] [labels] | labels [count] | escapes [count]");
+            std::process::exit(1);
+        }
+    };
+    io::stdout().write_all(output.as_bytes())
+}
@@ -0,0 +1,11 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use prometheus_exposition_format_rs::fuzzing::comment_parser;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(input) = std::str::from_utf8(data) {
+        if let Ok((rest, _)) = comment_parser(input) {
+            assert!(rest.len() < input.len());
+        }
+    }
+});
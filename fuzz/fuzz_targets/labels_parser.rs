@@ -0,0 +1,13 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use prometheus_exposition_format_rs::fuzzing::labels_parser;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(input) = std::str::from_utf8(data) {
+        if let Ok((_, labels)) = labels_parser(input) {
+            for (k, _) in labels {
+                assert!(!k.is_empty());
+            }
+        }
+    }
+});
@@ -0,0 +1,17 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use prometheus_exposition_format_rs::types::ParseOptions;
+use prometheus_exposition_format_rs::{
+    parse_complete_bytes, parse_complete_bytes_with_options, parse_reader,
+};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = parse_complete_bytes_with_options(data, &ParseOptions::lenient());
+    let res = parse_complete_bytes(data);
+    let lines = parse_reader(data);
+    // Metrics are compared through Debug as NaN values aren't equal to themselves
+    if let (Ok(res), Ok(lines)) = (&res, &lines) {
+        assert_eq!(format!("{:?}", res), format!("{:?}", lines));
+    }
+    assert_eq!(res.is_ok(), lines.is_ok());
+});
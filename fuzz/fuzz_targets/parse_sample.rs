@@ -0,0 +1,16 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use prometheus_exposition_format_rs::fuzzing::parse_sample;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(input) = std::str::from_utf8(data) {
+        if let Ok((rest, s)) = parse_sample(input) {
+            assert!(rest.len() < input.len());
+            assert!(!s.name.is_empty());
+            // The labels are only split and unescaped as they're iterated
+            for (k, _) in s.labels {
+                assert!(!k.is_empty());
+            }
+        }
+    }
+});
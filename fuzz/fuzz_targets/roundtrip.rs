@@ -0,0 +1,17 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use prometheus_exposition_format_rs::parse_complete;
+use prometheus_exposition_format_rs::render::render_metrics;
+
+fuzz_target!(|data: &[u8]| {
+    let input = match std::str::from_utf8(data) {
+        Ok(input) => input,
+        Err(_) => return,
+    };
+    if let Ok(metrics) = parse_complete(input) {
+        let rendered = render_metrics(&metrics);
+        let parsed = parse_complete(&rendered).expect("rendered metrics should parse");
+        // Metrics are compared through Debug as NaN values aren't equal to themselves
+        assert_eq!(format!("{:?}", parsed), format!("{:?}", metrics));
+    }
+});
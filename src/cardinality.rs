@@ -0,0 +1,230 @@
+//! Find what makes an exposition large, similar to the TSDB status page of Prometheus.
+use crate::render::{escape_label_value, families, write_name};
+use crate::types::Metric;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+/// Rough size of a sample once stored: its value, timestamp and a pointer to its labels
+const SAMPLE_OVERHEAD: usize = 24;
+
+/// The cardinality of an exposition, every list is sorted by decreasing count and truncated to the top entries
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub series: usize,
+    /// The number of series of each family, histograms and summaries include their `_bucket`, `_sum` and `_count` series
+    pub series_by_family: Vec<(String, usize)>,
+    /// The number of distinct values of each label name
+    pub values_by_label: Vec<(String, usize)>,
+    /// The number of series having each label pair
+    pub series_by_label_pair: Vec<(String, String, usize)>,
+    /// The bytes used by the distinct values of each label name
+    pub memory_by_label: Vec<(String, usize)>,
+    /// A rough estimation of the bytes needed to hold every series
+    pub estimated_memory: usize,
+}
+
+fn top<K: Ord>(mut v: Vec<(K, usize)>, n: usize) -> Vec<(K, usize)> {
+    v.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    v.truncate(n);
+    v
+}
+
+/// Analyze the cardinality of metrics, keeping the `n` largest entries of each list
+pub fn analyze(metrics: &[Metric], n: usize) -> Report {
+    let mut series = 0;
+    let mut series_by_family = Vec::new();
+    let mut values: HashMap<&str, HashSet<&str>> = HashMap::new();
+    let mut pairs: HashMap<(&str, &str), usize> = HashMap::new();
+    let mut estimated_memory = 0;
+    for f in families(metrics) {
+        let mut count = 0;
+        for m in std::iter::once(f.metric).chain(f.children.iter().copied()) {
+            for s in &m.samples {
+                count += 1;
+                estimated_memory += SAMPLE_OVERHEAD + m.name.len();
+                for (k, v) in s.labels.iter() {
+                    estimated_memory += k.len() + v.len();
+                    values.entry(k).or_default().insert(v);
+                    *pairs.entry((k, v)).or_default() += 1;
+                }
+            }
+        }
+        series += count;
+        series_by_family.push((f.metric.name.to_string(), count));
+    }
+    let memory_by_label = values
+        .iter()
+        .map(|(k, vs)| (k.to_string(), vs.iter().map(|v| v.len()).sum()))
+        .collect();
+    let values_by_label = values
+        .into_iter()
+        .map(|(k, vs)| (k.to_string(), vs.len()))
+        .collect();
+    let series_by_label_pair = top(
+        pairs
+            .into_iter()
+            .map(|((k, v), c)| ((k.to_string(), v.to_string()), c))
+            .collect(),
+        n,
+    );
+    Report {
+        series,
+        series_by_family: top(series_by_family, n),
+        values_by_label: top(values_by_label, n),
+        series_by_label_pair: series_by_label_pair
+            .into_iter()
+            .map(|((k, v), c)| (k, v, c))
+            .collect(),
+        memory_by_label: top(memory_by_label, n),
+        estimated_memory,
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut res = String::with_capacity(s.len() + 2);
+    res.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(res, "\\u{:04x}", c as u32);
+            }
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+fn json_counts(entries: &[(String, usize)]) -> String {
+    let items: Vec<String> = entries
+        .iter()
+        .map(|(k, c)| format!("{{\"name\":{},\"value\":{}}}", json_string(k), c))
+        .collect();
+    format!("[{}]", items.join(","))
+}
+
+impl Report {
+    /// Render the report as tables
+    pub fn render_text(&self) -> String {
+        let mut res = String::new();
+        let _ = writeln!(res, "Number of series: {}", self.series);
+        let _ = writeln!(res, "Estimated memory: {} bytes", self.estimated_memory);
+        let sections = [
+            ("Top series count by metric name", &self.series_by_family),
+            ("Top label names with value count", &self.values_by_label),
+            (
+                "Top label names with memory usage in bytes",
+                &self.memory_by_label,
+            ),
+        ];
+        for (title, entries) in sections.iter() {
+            let _ = writeln!(res, "\n{}:", title);
+            for (k, c) in entries.iter() {
+                let _ = write!(res, "{:>10} ", c);
+                let _ = write_name(&mut res, k);
+                res.push('\n');
+            }
+        }
+        let _ = writeln!(res, "\nTop series count by label value pairs:");
+        for (k, v, c) in &self.series_by_label_pair {
+            let _ = write!(res, "{:>10} ", c);
+            let _ = write_name(&mut res, k);
+            let _ = writeln!(res, "=\"{}\"", escape_label_value(v));
+        }
+        res
+    }
+
+    /// Render the report as a JSON object using the field names of the Prometheus TSDB status API
+    pub fn render_json(&self) -> String {
+        let pairs: Vec<(String, usize)> = self
+            .series_by_label_pair
+            .iter()
+            .map(|(k, v, c)| (format!("{}={}", k, v), *c))
+            .collect();
+        format!(
+            "{{\"numSeries\":{},\"estimatedMemoryInBytes\":{},\"seriesCountByMetricName\":{},\"labelValueCountByLabelName\":{},\"memoryInBytesByLabelName\":{},\"seriesCountByLabelValuePair\":{}}}",
+            self.series,
+            self.estimated_memory,
+            json_counts(&self.series_by_family),
+            json_counts(&self.values_by_label),
+            json_counts(&self.memory_by_label),
+            json_counts(&pairs)
+        )
+    }
+}
+
+#[cfg(test)]
+const EXAMPLE: &str = r#"# TYPE req histogram
+req_bucket{path="/a",le="1"} 1
+req_bucket{path="/a",le="+Inf"} 2
+req_sum{path="/a"} 3
+req_count{path="/a"} 2
+up{instance="a"} 1
+up{instance="b"} 1
+up{instance="c"} 0
+"#;
+
+#[test]
+fn test_analyze() {
+    let report = analyze(&crate::parse_complete(EXAMPLE).unwrap(), 2);
+    assert_eq!(report.series, 7);
+    assert_eq!(
+        report.series_by_family,
+        vec![("req".to_string(), 4), ("up".to_string(), 3)]
+    );
+    assert_eq!(
+        report.values_by_label,
+        vec![("instance".to_string(), 3), ("le".to_string(), 2)]
+    );
+    assert_eq!(
+        report.series_by_label_pair,
+        vec![
+            ("path".to_string(), "/a".to_string(), 4),
+            ("instance".to_string(), "a".to_string(), 1)
+        ]
+    );
+    assert_eq!(
+        report.memory_by_label,
+        vec![("le".to_string(), 5), ("instance".to_string(), 3)]
+    );
+    assert!(report.estimated_memory > 0);
+}
+
+#[test]
+fn test_render() {
+    let report = analyze(&crate::parse_complete(EXAMPLE).unwrap(), 1);
+    assert_eq!(
+        report.render_text(),
+        format!(
+            "Number of series: 7\nEstimated memory: {} bytes\n\nTop series count by metric name:\n         4 req\n\nTop label names with value count:\n         3 instance\n\nTop label names with memory usage in bytes:\n         5 le\n\nTop series count by label value pairs:\n         4 path=\"/a\"\n",
+            report.estimated_memory
+        )
+    );
+    assert_eq!(
+        report.render_json(),
+        format!(
+            "{{\"numSeries\":7,\"estimatedMemoryInBytes\":{},\"seriesCountByMetricName\":[{{\"name\":\"req\",\"value\":4}}],\"labelValueCountByLabelName\":[{{\"name\":\"instance\",\"value\":3}}],\"memoryInBytesByLabelName\":[{{\"name\":\"le\",\"value\":5}}],\"seriesCountByLabelValuePair\":[{{\"name\":\"path=/a\",\"value\":4}}]}}",
+            report.estimated_memory
+        )
+    );
+    assert_eq!(json_string("a\"\\\n\u{1}"), "\"a\\\"\\\\\\n\\u0001\"");
+
+    // Names and values are escaped like in the exposition
+    let report = analyze(
+        &crate::parse_complete("{\"a.b\",\"c d\"=\"x\\\"\\n\"} 1\n").unwrap(),
+        1,
+    );
+    let text = report.render_text();
+    assert!(text.contains("\n         1 \"a.b\"\n"), "{}", text);
+    assert!(text.contains("\n         1 \"c d\"\n"), "{}", text);
+    assert!(
+        text.ends_with("\n         1 \"c d\"=\"x\\\"\\n\"\n"),
+        "{}",
+        text
+    );
+}
@@ -0,0 +1,226 @@
+use crate::render::write_line;
+use crate::types::{Err, Limit, Line, ParseOptions};
+use crate::LinePosition;
+use bytes::{BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// A codec framing a byte stream as lines of the exposition format.
+/// Decoding returns a `Line` for each complete line, partial lines are kept until the rest of the line is received.
+/// A partial line is rejected as soon as it can't fit the body size limit of the options or the maximum line length.
+#[derive(Default)]
+pub struct ExpositionCodec {
+    options: ParseOptions,
+    max_line_length: Option<usize>,
+    position: LinePosition,
+    buf: Vec<u8>,
+    // Where to start looking for the next line break to not search the same bytes again
+    next_index: usize,
+}
+
+impl ExpositionCodec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_options(options: ParseOptions) -> Self {
+        ExpositionCodec {
+            options,
+            ..Self::default()
+        }
+    }
+
+    /// A codec rejecting lines longer than `max_line_length` bytes, the line break isn't counted
+    pub fn with_max_line_length(options: ParseOptions, max_line_length: usize) -> Self {
+        ExpositionCodec {
+            options,
+            max_line_length: Some(max_line_length),
+            ..Self::default()
+        }
+    }
+
+    /// Check the length of the line being decoded, before it's complete
+    fn check_length(&self, len: usize) -> Result<(), Err> {
+        let exceeded = |limit| Err::LimitExceeded {
+            limit,
+            line: self.position.line + 1,
+        };
+        if let Some(l) = self.max_line_length.filter(|&l| len > l) {
+            return Err(exceeded(Limit::LineLength(l)));
+        }
+        match self.options.body_size_limit {
+            Some(l) if self.position.offset + len > l => Err(exceeded(Limit::BodySize(l))),
+            _ => Ok(()),
+        }
+    }
+
+    fn parse(&mut self, line: &[u8]) -> Result<Line, Err> {
+        self.buf.clear();
+        self.buf.extend_from_slice(line);
+        Ok(self.position.parse(&mut self.buf, &self.options)?.into())
+    }
+}
+
+impl Decoder for ExpositionCodec {
+    type Item = Line;
+    type Error = Err;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Line>, Err> {
+        match src[self.next_index..].iter().position(|&b| b == b'\n') {
+            Some(i) => {
+                self.check_length(self.next_index + i)?;
+                let line = src.split_to(self.next_index + i + 1);
+                self.next_index = 0;
+                self.parse(&line).map(Some)
+            }
+            None => {
+                self.check_length(src.len())?;
+                self.next_index = src.len();
+                Ok(None)
+            }
+        }
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Line>, Err> {
+        if let Some(l) = self.decode(src)? {
+            return Ok(Some(l));
+        }
+        if src.is_empty() {
+            return Ok(None);
+        }
+        // The last line isn't terminated, it's only accepted by lenient options
+        self.check_length(src.len())?;
+        let line = src.split();
+        self.next_index = 0;
+        self.parse(&line).map(Some)
+    }
+}
+
+impl Encoder<Line> for ExpositionCodec {
+    type Error = Err;
+
+    fn encode(&mut self, line: Line, dst: &mut BytesMut) -> Result<(), Err> {
+        let mut s = String::new();
+        write_line(&mut s, &line).expect("Writing to a string never fails");
+        dst.put_slice(s.as_bytes());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn decode_all(codec: &mut ExpositionCodec, chunks: &[&[u8]]) -> Vec<Result<Line, Err>> {
+    let mut buf = BytesMut::new();
+    let mut res = Vec::new();
+    for chunk in chunks {
+        buf.extend_from_slice(chunk);
+        while let Some(l) = codec.decode(&mut buf).transpose() {
+            let stop = l.is_err();
+            res.push(l);
+            if stop {
+                return res;
+            }
+        }
+    }
+    while let Some(l) = codec.decode_eof(&mut buf).transpose() {
+        let stop = l.is_err();
+        res.push(l);
+        if stop {
+            break;
+        }
+    }
+    res
+}
+
+#[test]
+fn test_decode_partial_lines() {
+    use crate::types::{MetricType, Sample};
+    let mut codec = ExpositionCodec::new();
+    let res: Vec<Line> = decode_all(
+        &mut codec,
+        &[
+            b"# HELP foo Some\\nhelp\n# TY",
+            b"PE foo counter\r",
+            b"\nfoo{a=\"b\"} 1",
+            b" 123\n\n# Just a comment\n",
+        ],
+    )
+    .into_iter()
+    .collect::<Result<_, _>>()
+    .unwrap();
+    assert_eq!(
+        res,
+        vec![
+            Line::Help {
+                name: "foo".to_string(),
+                doc: "Some\nhelp".to_string()
+            },
+            Line::Type {
+                name: "foo".to_string(),
+                data_type: MetricType::Counter
+            },
+            Line::Sample {
+                name: "foo".to_string(),
+                sample: Sample::new(1f64, Some(123), vec!["a", "b"])
+            },
+            Line::Empty,
+            Line::Comment(" Just a comment".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn test_decode_eof() {
+    let res = decode_all(&mut ExpositionCodec::new(), &[b"foo 1\nbar", b" 2"]);
+    assert_eq!(res.len(), 2);
+    assert!(res[1].is_err());
+
+    let res = decode_all(
+        &mut ExpositionCodec::with_options(ParseOptions::lenient()),
+        &[b"foo 1\nbar", b" 2"],
+    );
+    assert_eq!(res.len(), 2);
+    assert!(res.iter().all(|l| l.is_ok()));
+}
+
+#[test]
+fn test_decode_limits() {
+    let exceeded = |res: &[Result<Line, Err>]| match res.last() {
+        Some(Result::Err(Err::LimitExceeded { limit, line })) => Some((*limit, *line)),
+        _ => None,
+    };
+    // A line without its end is rejected once it's too long
+    let mut codec = ExpositionCodec::with_max_line_length(ParseOptions::default(), 8);
+    let mut buf = BytesMut::from(&b"a 1\nbcdefgh"[..]);
+    assert!(codec.decode(&mut buf).unwrap().is_some());
+    assert!(codec.decode(&mut buf).unwrap().is_none());
+    buf.extend_from_slice(b"ij");
+    assert!(matches!(
+        codec.decode(&mut buf),
+        Result::Err(Err::LimitExceeded {
+            limit: Limit::LineLength(8),
+            line: 2
+        })
+    ));
+
+    let mut codec = ExpositionCodec::with_max_line_length(ParseOptions::default(), 5);
+    let res = decode_all(&mut codec, &[b"a 123\nb 1234\n"]);
+    assert_eq!(exceeded(&res), Some((Limit::LineLength(5), 2)));
+
+    let options = ParseOptions {
+        body_size_limit: Some(6),
+        ..ParseOptions::default()
+    };
+    let res = decode_all(&mut ExpositionCodec::with_options(options), &[b"a 1\nbcd"]);
+    assert_eq!(exceeded(&res), Some((Limit::BodySize(6), 2)));
+}
+
+#[test]
+fn test_encode_round_trip() {
+    let input =
+        "# HELP foo Some\\\\help\n# TYPE foo gauge\nfoo{a=\"b\\\"c\"} -Inf 12\n\n# A comment\n";
+    let mut codec = ExpositionCodec::new();
+    let mut buf = BytesMut::new();
+    for l in decode_all(&mut codec, &[input.as_bytes()]) {
+        codec.encode(l.unwrap(), &mut buf).unwrap();
+    }
+    assert_eq!(&buf[..], input.as_bytes());
+}
@@ -1,26 +1,29 @@
-use crate::common::token_parser;
+use crate::common::name_parser;
 use crate::types::MetricType;
 use nom::branch::alt;
 use nom::bytes::complete::tag;
 use nom::character::complete::not_line_ending;
-use nom::character::complete::{newline, space0, space1};
-use nom::combinator::{map, opt};
+use nom::character::complete::{line_ending, space0, space1};
+use nom::combinator::{map, not, opt};
 #[cfg(test)]
 use nom::error::ErrorKind;
 use nom::sequence::{delimited, preceded, tuple};
 #[cfg(test)]
 use nom::Err::Error;
 use nom::IResult;
+use std::borrow::Cow;
 
 #[derive(Debug, PartialEq)]
 pub enum CommentType<'a> {
-    Type(&'a str, MetricType),
-    Help(&'a str),
-    Other,
+    Type(Cow<'a, str>, MetricType),
+    /// The metric name and its raw (still escaped) docstring
+    Help(Cow<'a, str>, &'a str),
+    /// The text following the `#`
+    Other(&'a str),
 }
 
 /// Parse comments that starts with "# TYPE"
-fn type_parser(i: &str) -> IResult<&str, (&str, MetricType)> {
+fn type_parser(i: &str) -> IResult<&str, (Cow<'_, str>, MetricType)> {
     let metric_parser = map(
         opt(preceded(
             space1,
@@ -37,31 +40,69 @@ fn type_parser(i: &str) -> IResult<&str, (&str, MetricType)> {
 
     delimited(
         tuple((tag("#"), space1, tag("TYPE"), space1)),
-        tuple((token_parser, metric_parser)),
-        tuple((space0, newline)),
+        tuple((name_parser, metric_parser)),
+        tuple((space0, line_ending)),
     )(i)
 }
 
-fn other_comment_parser(i: &str) -> IResult<&str, ()> {
-    map(delimited(tag("#"), not_line_ending, newline), |_| ())(i)
+/// Parse the start of a `# TYPE` or `# HELP` line, like in Prometheus the rest of such a line must be valid
+/// and it isn't read as a plain comment otherwise
+fn keyword_parser(i: &str) -> IResult<&str, &str> {
+    delimited(
+        tuple((tag("#"), space1)),
+        alt((tag("TYPE"), tag("HELP"))),
+        space1,
+    )(i)
+}
+
+fn other_comment_parser(i: &str) -> IResult<&str, &str> {
+    delimited(tag("#"), not_line_ending, line_ending)(i)
 }
 
 /// Parse comments that starts with "# HELP"
-fn help_parser(i: &str) -> IResult<&str, &str> {
+fn help_parser(i: &str) -> IResult<&str, (Cow<'_, str>, &str)> {
     delimited(
         tuple((tag("#"), space1, tag("HELP"), space1)),
-        not_line_ending,
-        newline,
+        tuple((
+            name_parser,
+            map(opt(preceded(space1, not_line_ending)), |x| x.unwrap_or("")),
+        )),
+        line_ending,
     )(i)
 }
 
+/// Unescape a HELP docstring, only `\\` and `\n` are escape sequences
+pub fn unescape_help(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            res.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => res.push('\n'),
+            Some('\\') => res.push('\\'),
+            Some(o) => {
+                res.push('\\');
+                res.push(o);
+            }
+            None => res.push('\\'),
+        }
+    }
+    res
+}
+
 /// Parses a comment and return the different types
 /// TODO make help optional
-pub fn comment_parser(i: &str) -> IResult<&str, CommentType> {
+pub fn comment_parser(i: &str) -> IResult<&str, CommentType<'_>> {
     alt((
         map(type_parser, |(name, tpe)| CommentType::Type(name, tpe)),
-        map(help_parser, |s| CommentType::Help(s)),
-        map(other_comment_parser, |_| CommentType::Other),
+        map(help_parser, |(name, doc)| CommentType::Help(name, doc)),
+        map(
+            preceded(not(keyword_parser), other_comment_parser),
+            CommentType::Other,
+        ),
     ))(i)
 }
 
@@ -70,30 +111,56 @@ pub fn comment_parser(i: &str) -> IResult<&str, CommentType> {
 fn test_type_parser() {
     assert_eq!(
         type_parser("# TYPE http_request_duration_seconds histogram\n"),
-        Ok(("", ("http_request_duration_seconds", MetricType::Histogram)))
+        Ok((
+            "",
+            (
+                "http_request_duration_seconds".into(),
+                MetricType::Histogram
+            )
+        ))
     );
     assert_eq!(
         type_parser("# TYPE http_request_duration_seconds\n"),
-        Ok(("", ("http_request_duration_seconds", MetricType::Untyped)))
+        Ok((
+            "",
+            ("http_request_duration_seconds".into(), MetricType::Untyped)
+        ))
     );
     assert_eq!(
         type_parser("# TYPE http_request_duration_seconds   \n"),
-        Ok(("", ("http_request_duration_seconds", MetricType::Untyped)))
+        Ok((
+            "",
+            ("http_request_duration_seconds".into(), MetricType::Untyped)
+        ))
     );
     assert_eq!(
         type_parser("# TYPE http_request_duration_seconds   \nfoo"),
         Ok((
             "foo",
-            ("http_request_duration_seconds", MetricType::Untyped)
+            ("http_request_duration_seconds".into(), MetricType::Untyped)
         ))
     );
     assert_eq!(
         type_parser("# TYPE http_request_duration_seconds   summary\n"),
-        Ok(("", ("http_request_duration_seconds", MetricType::Summary)))
+        Ok((
+            "",
+            ("http_request_duration_seconds".into(), MetricType::Summary)
+        ))
+    );
+    assert_eq!(
+        type_parser("# TYPE http_request_duration_seconds counter\r\nfoo"),
+        Ok((
+            "foo",
+            ("http_request_duration_seconds".into(), MetricType::Counter)
+        ))
+    );
+    assert_eq!(
+        type_parser("# TYPE \"my.metric\" gauge\n"),
+        Ok(("", ("my.metric".into(), MetricType::Gauge)))
     );
     assert_eq!(
         type_parser("# TYPE http_request_duration_seconds sometype\n"),
-        Err(Error(("sometype\n", ErrorKind::Char)))
+        Err(Error(("sometype\n", ErrorKind::CrLf)))
     );
 }
 
@@ -101,15 +168,19 @@ fn test_type_parser() {
 fn test_other_comment_parser() {
     assert_eq!(
         other_comment_parser("# TYPE http_request_duration_seconds histogram\n"),
-        Ok(("", ()))
+        Ok(("", " TYPE http_request_duration_seconds histogram"))
     );
     assert_eq!(
         other_comment_parser("# TYPE http_request_duration_seconds histogram\nfoo"),
-        Ok(("foo", ()))
+        Ok(("foo", " TYPE http_request_duration_seconds histogram"))
     );
     assert_eq!(
         other_comment_parser("#This is a comment and we don't care about it\n"),
-        Ok(("", ()))
+        Ok(("", "This is a comment and we don't care about it"))
+    );
+    assert_eq!(
+        other_comment_parser("#This is a comment\r\nfoo"),
+        Ok(("foo", "This is a comment"))
     );
     assert_eq!(
         other_comment_parser("foo bar\n"),
@@ -128,7 +199,30 @@ fn test_help_parser() {
     );
     assert_eq!(
         help_parser("# HELP http_request_duration_seconds histogram\nfoo"),
-        Ok(("foo", "http_request_duration_seconds histogram"))
+        Ok(("foo", ("http_request_duration_seconds".into(), "histogram")))
+    );
+    assert_eq!(
+        help_parser("# HELP http_requests_total The total number of HTTP requests.\n"),
+        Ok((
+            "",
+            (
+                "http_requests_total".into(),
+                "The total number of HTTP requests."
+            )
+        ))
+    );
+    assert_eq!(
+        help_parser("# HELP \"a \\\"quoted\\\" name\" doc\n"),
+        Ok(("", ("a \"quoted\" name".into(), "doc")))
+    );
+    // Empty docstrings
+    assert_eq!(
+        help_parser("# HELP http_requests_total\n"),
+        Ok(("", ("http_requests_total".into(), "")))
+    );
+    assert_eq!(
+        help_parser("# HELP http_request_duration_seconds histogram\r\nfoo"),
+        Ok(("foo", ("http_request_duration_seconds".into(), "histogram")))
     );
     assert_eq!(
         help_parser("# This is a comment and we don't care about it\n"),
@@ -147,17 +241,45 @@ fn test_comment_parser() {
     );
     assert_eq!(
         comment_parser("# http_request_duration_seconds histogram\n"),
-        Ok(("", CommentType::Other))
+        Ok((
+            "",
+            CommentType::Other(" http_request_duration_seconds histogram")
+        ))
     );
     assert_eq!(
         comment_parser("# HELP some info\n"),
-        Ok(("", CommentType::Help("some info")))
+        Ok(("", CommentType::Help("some".into(), "info")))
     );
     assert_eq!(
         comment_parser("# TYPE http_request_duration_seconds histogram\n"),
         Ok((
             "",
-            CommentType::Type("http_request_duration_seconds", MetricType::Histogram,)
+            CommentType::Type(
+                "http_request_duration_seconds".into(),
+                MetricType::Histogram,
+            )
         ))
     );
+    // A TYPE or HELP line without a valid name or type isn't a plain comment
+    for s in &["# TYPE #\n", "# TYPE \n", "#\tHELP \n", "# TYPE a bogus\n"] {
+        assert_eq!(comment_parser(s), Err(Error((*s, ErrorKind::Not))));
+    }
+    // Without a blank after them they're other words
+    assert_eq!(
+        comment_parser("# TYPE\n"),
+        Ok(("", CommentType::Other(" TYPE")))
+    );
+    assert_eq!(
+        comment_parser("# HELPER text\n"),
+        Ok(("", CommentType::Other(" HELPER text")))
+    );
+}
+
+#[test]
+fn test_unescape_help() {
+    assert_eq!(unescape_help("simple"), "simple");
+    assert_eq!(unescape_help("a\\nb"), "a\nb");
+    assert_eq!(unescape_help("C:\\\\DIR"), "C:\\DIR");
+    // Other sequences are kept as is
+    assert_eq!(unescape_help("a\\\"b\\"), "a\\\"b\\");
 }
@@ -1,13 +1,14 @@
-use nom::bytes::complete::take;
-use nom::bytes::complete::{take_while, take_while1};
-use nom::character::complete::line_ending;
-use nom::combinator::{map, peek};
+use nom::branch::alt;
+use nom::bytes::complete::{escaped, take_while, take_while1};
+use nom::character::complete::{char, line_ending, none_of, one_of};
+use nom::combinator::{map, opt, recognize, verify};
 #[cfg(test)]
 use nom::error::ErrorKind;
-use nom::sequence::{pair, terminated};
+use nom::sequence::{delimited, pair, terminated};
 #[cfg(test)]
 use nom::Err::Error;
 use nom::IResult;
+use std::borrow::Cow;
 
 fn is_simple(x: char) -> bool {
     x.is_alphabetic() || x == '_' || x == ':'
@@ -17,12 +18,61 @@ fn is_simple(x: char) -> bool {
 /// https://prometheus.io/docs/concepts/data_model/#metric-names-and-labels
 /// Should match regex: `[a-zA-Z_:][a-zA-Z0-9_:]*`
 pub fn token_parser(i: &str) -> IResult<&str, &str> {
-    let (_, (st, end)) = peek(pair(
+    recognize(pair(
         take_while1(is_simple),
         take_while(|x| is_simple(x) || x.is_alphanumeric()),
-    ))(i)?;
+    ))(i)
+}
+
+/// Whether a name can be written without quotes, like Prometheus before 3.0 required.
+/// The parsers also accept other alphabetic characters but rendering quotes them to stay compatible.
+pub fn is_legacy_name(name: &str) -> bool {
+    let mut bytes = name.bytes();
+    let is_start = |b: u8| b.is_ascii_alphabetic() || b == b'_' || b == b':';
+    bytes.next().is_some_and(is_start) && bytes.all(|b| is_start(b) || b.is_ascii_digit())
+}
+
+/// Unescape a quoted string, `\\`, `\"` and `\n` are the only escape sequences
+pub fn unescape_quoted(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => res.push(match chars.next() {
+                Some('n') => '\n',
+                Some(c) => c,
+                None => break,
+            }),
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+/// Parse a quoted name, Prometheus 3.0 allows any non empty UTF-8 name written like a label value.
+/// The name is only copied when it contains escape sequences.
+pub fn quoted_name_parser(i: &str) -> IResult<&str, Cow<'_, str>> {
+    let raw = delimited(
+        char('"'),
+        opt(escaped(none_of("\n\"\\"), '\\', one_of("n\"\\"))),
+        char('"'),
+    );
+    map(
+        verify(raw, |s: &Option<&str>| s.is_some_and(|s| !s.is_empty())),
+        |s| {
+            let s = s.unwrap_or_default();
+            if s.contains('\\') {
+                Cow::Owned(unescape_quoted(s))
+            } else {
+                Cow::Borrowed(s)
+            }
+        },
+    )(i)
+}
 
-    take(st.len() + end.len())(i)
+/// Parse a metric or label name, either a token or a quoted name
+pub fn name_parser(i: &str) -> IResult<&str, Cow<'_, str>> {
+    alt((map(token_parser, Cow::Borrowed), quoted_name_parser))(i)
 }
 
 /// Parse empty lines (lines with only whitespaces)
@@ -44,14 +94,14 @@ fn test_token_parser() {
     ok_token("foo_0:3");
     ok_token(":foo");
     assert_eq!(
-        token_parser(&"33"),
+        token_parser("33"),
         Err(Error(("33", ErrorKind::TakeWhile1)))
     );
     assert_eq!(
-        token_parser(&")3"),
+        token_parser(")3"),
         Err(Error((")3", ErrorKind::TakeWhile1)))
     );
-    assert_eq!(token_parser(&"a("), Ok(("(", "a")));
+    assert_eq!(token_parser("a("), Ok(("(", "a")));
 }
 
 #[test]
@@ -60,8 +110,28 @@ fn test_empty_line_parser() {
     assert_eq!(empty_line_parser("\t\n"), Ok(("", ())));
     assert_eq!(empty_line_parser(" \t \n"), Ok(("", ())));
     assert_eq!(empty_line_parser("      \n     \n"), Ok(("     \n", ())));
+    assert_eq!(empty_line_parser(" \r\nfoo"), Ok(("foo", ())));
     assert_eq!(
         empty_line_parser("      "),
         Err(Error(("", ErrorKind::CrLf)))
     );
 }
+
+#[test]
+fn test_name_parser() {
+    assert_eq!(name_parser("abc d"), Ok((" d", Cow::Borrowed("abc"))));
+    assert_eq!(name_parser("\"a.b c\"d"), Ok(("d", Cow::Borrowed("a.b c"))));
+    assert_eq!(
+        name_parser("\"a\\\"b\\nc\\\\\""),
+        Ok(("", Cow::Owned("a\"b\nc\\".to_string())))
+    );
+    assert!(name_parser("\"\"").is_err());
+    assert!(name_parser("\"a\\tb\"").is_err());
+    assert!(name_parser("\"a").is_err());
+
+    assert!(is_legacy_name("a_b:c0"));
+    assert!(!is_legacy_name("0a"));
+    assert!(!is_legacy_name("a.b"));
+    assert!(!is_legacy_name("é"));
+    assert!(!is_legacy_name(""));
+}
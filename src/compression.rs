@@ -0,0 +1,202 @@
+use crate::parse_reader_with_options;
+use crate::types::{Err, Metric, ParseOptions};
+#[cfg(any(
+    feature = "gzip",
+    feature = "deflate",
+    feature = "zstd",
+    feature = "snappy"
+))]
+use std::io::BufReader;
+use std::io::{self, BufRead};
+
+/// Compression applied to an exposition body.
+/// Decompressing requires the feature with the same name (`gzip`, `deflate`, `zstd` or `snappy`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Identity,
+    Gzip,
+    /// Zlib wrapped deflate like the `deflate` HTTP content encoding
+    Deflate,
+    Zstd,
+    /// Snappy framing format
+    Snappy,
+}
+
+impl Encoding {
+    /// Read the value of a `Content-Encoding` header, returns `None` for unknown encodings
+    pub fn from_content_encoding(s: &str) -> Option<Encoding> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "" | "identity" => Some(Encoding::Identity),
+            "gzip" | "x-gzip" => Some(Encoding::Gzip),
+            "deflate" => Some(Encoding::Deflate),
+            "zstd" => Some(Encoding::Zstd),
+            "snappy" | "x-snappy-framed" => Some(Encoding::Snappy),
+            _ => None,
+        }
+    }
+
+    /// Guess the encoding from the first bytes of a body using the magic number of gzip, zstd and snappy.
+    /// None of these magic numbers is valid UTF-8 so text can't be mistaken for them.
+    /// Deflate is never detected: its two bytes header also matches text like `hb` or `HK`,
+    /// it has to be given with `parse_encoded`, e.g. from the `Content-Encoding` header.
+    pub fn detect(prefix: &[u8]) -> Encoding {
+        match prefix {
+            [0x1f, 0x8b, ..] => Encoding::Gzip,
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Encoding::Zstd,
+            [0xff, 0x06, 0x00, 0x00, b's', b'N', b'a', b'P', b'p', b'Y', ..] => Encoding::Snappy,
+            _ => Encoding::Identity,
+        }
+    }
+}
+
+fn unsupported(encoding: Encoding) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{:?} decompression requires enabling its feature", encoding),
+    )
+}
+
+/// Wrap a reader so that reading from it returns the decompressed body.
+/// Decompression is streamed, the whole body is never held in memory.
+pub fn decoder<'a, R: BufRead + 'a>(
+    reader: R,
+    encoding: Encoding,
+) -> io::Result<Box<dyn BufRead + 'a>> {
+    match encoding {
+        Encoding::Identity => Ok(Box::new(reader)),
+        #[cfg(feature = "gzip")]
+        Encoding::Gzip => Ok(Box::new(BufReader::new(
+            flate2::bufread::MultiGzDecoder::new(reader),
+        ))),
+        #[cfg(feature = "deflate")]
+        Encoding::Deflate => Ok(Box::new(BufReader::new(flate2::bufread::ZlibDecoder::new(
+            reader,
+        )))),
+        #[cfg(feature = "zstd")]
+        Encoding::Zstd => Ok(Box::new(BufReader::new(
+            zstd::stream::read::Decoder::with_buffer(reader)?,
+        ))),
+        #[cfg(feature = "snappy")]
+        Encoding::Snappy => Ok(Box::new(BufReader::new(snap::read::FrameDecoder::new(
+            reader,
+        )))),
+        #[allow(unreachable_patterns)]
+        e => Err(unsupported(e)),
+    }
+}
+
+/// Parse a body compressed with a known encoding (e.g. from the `Content-Encoding` header).
+pub fn parse_encoded<R: BufRead>(
+    reader: R,
+    encoding: Encoding,
+    options: &ParseOptions,
+) -> Result<Vec<Metric>, Err> {
+    parse_reader_with_options(decoder(reader, encoding).map_err(Err::Io)?, options)
+}
+
+/// Parse a body that may be compressed with gzip, zstd or snappy, the encoding is detected from its first bytes.
+pub fn parse_compressed<R: BufRead>(
+    mut reader: R,
+    options: &ParseOptions,
+) -> Result<Vec<Metric>, Err> {
+    let encoding = Encoding::detect(reader.fill_buf().map_err(Err::Io)?);
+    parse_encoded(reader, encoding, options)
+}
+
+#[cfg(test)]
+const EXAMPLE: &str = "# TYPE foo counter\nfoo{a=\"b\"} 1\nbar 2\n";
+
+#[cfg(test)]
+fn assert_same_as_text(compressed: &[u8], encoding: Encoding) {
+    let expected = crate::parse_complete(EXAMPLE).unwrap();
+    assert_eq!(Encoding::detect(compressed), encoding);
+    assert_eq!(
+        parse_encoded(compressed, encoding, &ParseOptions::default()).unwrap(),
+        expected
+    );
+    assert_eq!(
+        parse_compressed(compressed, &ParseOptions::default()).unwrap(),
+        expected
+    );
+}
+
+#[test]
+fn test_content_encoding() {
+    assert_eq!(
+        Encoding::from_content_encoding(""),
+        Some(Encoding::Identity)
+    );
+    assert_eq!(
+        Encoding::from_content_encoding("GZIP"),
+        Some(Encoding::Gzip)
+    );
+    assert_eq!(
+        Encoding::from_content_encoding("zstd"),
+        Some(Encoding::Zstd)
+    );
+    assert_eq!(Encoding::from_content_encoding("br"), None);
+}
+
+#[test]
+fn test_identity() {
+    assert_same_as_text(EXAMPLE.as_bytes(), Encoding::Identity);
+    // Text matching a zlib header isn't taken for deflate
+    for text in &[
+        "x_total 1\n",
+        "hbase_regions 1\n",
+        "HK 1\n",
+        "Xf 1\n",
+        "Hj 1\n",
+    ] {
+        assert_eq!(Encoding::detect(text.as_bytes()), Encoding::Identity);
+        assert_eq!(
+            parse_compressed(text.as_bytes(), &ParseOptions::default()).unwrap(),
+            crate::parse_complete(text).unwrap()
+        );
+    }
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn test_gzip() {
+    use std::io::Write;
+    let mut e = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    e.write_all(EXAMPLE.as_bytes()).unwrap();
+    assert_same_as_text(&e.finish().unwrap(), Encoding::Gzip);
+}
+
+#[cfg(feature = "deflate")]
+#[test]
+fn test_deflate() {
+    use std::io::Write;
+    let mut e = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    e.write_all(EXAMPLE.as_bytes()).unwrap();
+    // Only known from the content encoding
+    assert_eq!(
+        parse_encoded(
+            &e.finish().unwrap()[..],
+            Encoding::Deflate,
+            &ParseOptions::default()
+        )
+        .unwrap(),
+        crate::parse_complete(EXAMPLE).unwrap()
+    );
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn test_zstd() {
+    assert_same_as_text(
+        &zstd::stream::encode_all(EXAMPLE.as_bytes(), 0).unwrap(),
+        Encoding::Zstd,
+    );
+}
+
+#[cfg(feature = "snappy")]
+#[test]
+fn test_snappy() {
+    use std::io::Write;
+    let mut e = snap::write::FrameEncoder::new(Vec::new());
+    e.write_all(EXAMPLE.as_bytes()).unwrap();
+    assert_same_as_text(&e.into_inner().unwrap(), Encoding::Snappy);
+}
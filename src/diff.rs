@@ -0,0 +1,301 @@
+//! Compare two expositions, for example the scrapes of an exporter before and after an upgrade.
+use crate::labels::Labels;
+use crate::render::{families, write_help, write_sample, write_type, Family};
+use crate::types::{Metric, MetricType, Sample};
+use std::collections::BTreeMap;
+
+/// Options for `diff`
+#[derive(Debug, Clone, Default)]
+pub struct DiffOptions {
+    /// Values differing by at most this much are considered equal
+    pub tolerance: f64,
+    /// Values differing by at most this fraction of the old value are considered equal
+    pub relative_tolerance: f64,
+}
+
+/// A difference between two expositions, samples include the name of the series which can be a histogram or summary child
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    FamilyAdded {
+        name: String,
+        data_type: MetricType,
+    },
+    FamilyRemoved {
+        name: String,
+        data_type: MetricType,
+    },
+    TypeChanged {
+        name: String,
+        old: MetricType,
+        new: MetricType,
+    },
+    HelpChanged {
+        name: String,
+        old: Option<String>,
+        new: Option<String>,
+    },
+    SeriesAdded {
+        name: String,
+        sample: Sample,
+    },
+    SeriesRemoved {
+        name: String,
+        sample: Sample,
+    },
+    ValueChanged {
+        name: String,
+        old: Sample,
+        new: Sample,
+    },
+}
+
+type SeriesKey<'a> = (&'a str, &'a Labels);
+
+fn series<'a>(f: &Family<'a>) -> BTreeMap<SeriesKey<'a>, &'a Sample> {
+    let mut res = BTreeMap::new();
+    for m in std::iter::once(f.metric).chain(f.children.iter().copied()) {
+        for s in &m.samples {
+            res.insert((&*m.name, &s.labels), s);
+        }
+    }
+    res
+}
+
+fn value_changed(old: f64, new: f64, options: &DiffOptions) -> bool {
+    if old.is_nan() || new.is_nan() {
+        return old.is_nan() != new.is_nan();
+    }
+    // Tolerances can't be applied to infinities, the deltas would be infinite or NaN
+    if old.is_infinite() || new.is_infinite() {
+        return old != new;
+    }
+    if old == new {
+        return false;
+    }
+    let delta = (new - old).abs();
+    delta > options.tolerance && delta > options.relative_tolerance * old.abs()
+}
+
+fn diff_series(changes: &mut Vec<Change>, old: &Family, new: &Family, options: &DiffOptions) {
+    let old = series(old);
+    let new = series(new);
+    for ((name, labels), s) in &old {
+        match new.get(&(*name, *labels)) {
+            None => changes.push(Change::SeriesRemoved {
+                name: name.to_string(),
+                sample: (*s).clone(),
+            }),
+            Some(n) if value_changed(s.value, n.value, options) => {
+                changes.push(Change::ValueChanged {
+                    name: name.to_string(),
+                    old: (*s).clone(),
+                    new: (*n).clone(),
+                })
+            }
+            Some(_) => {}
+        }
+    }
+    for ((name, labels), s) in &new {
+        if !old.contains_key(&(*name, *labels)) {
+            changes.push(Change::SeriesAdded {
+                name: name.to_string(),
+                sample: (*s).clone(),
+            });
+        }
+    }
+}
+
+/// Compare two expositions family by family.
+/// The series of added and removed families are reported as added and removed too.
+pub fn diff(old: &[Metric], new: &[Metric], options: &DiffOptions) -> Vec<Change> {
+    let empty = Metric::new("", MetricType::Untyped);
+    let nothing = Family {
+        metric: &empty,
+        children: Vec::new(),
+    };
+    let old: BTreeMap<&str, Family> = families(old)
+        .into_iter()
+        .map(|f| (&*f.metric.name, f))
+        .collect();
+    let new: BTreeMap<&str, Family> = families(new)
+        .into_iter()
+        .map(|f| (&*f.metric.name, f))
+        .collect();
+    let mut names: Vec<&str> = old.keys().chain(new.keys()).copied().collect();
+    names.sort_unstable();
+    names.dedup();
+
+    let mut changes = Vec::new();
+    for name in names {
+        match (old.get(name), new.get(name)) {
+            (Some(o), Some(n)) => {
+                if o.metric.data_type != n.metric.data_type {
+                    changes.push(Change::TypeChanged {
+                        name: name.to_string(),
+                        old: o.metric.data_type.clone(),
+                        new: n.metric.data_type.clone(),
+                    });
+                }
+                if o.metric.help != n.metric.help {
+                    changes.push(Change::HelpChanged {
+                        name: name.to_string(),
+                        old: o.metric.help.clone(),
+                        new: n.metric.help.clone(),
+                    });
+                }
+                diff_series(&mut changes, o, n, options);
+            }
+            (Some(o), None) => {
+                changes.push(Change::FamilyRemoved {
+                    name: name.to_string(),
+                    data_type: o.metric.data_type.clone(),
+                });
+                diff_series(&mut changes, o, &nothing, options);
+            }
+            (None, Some(n)) => {
+                changes.push(Change::FamilyAdded {
+                    name: name.to_string(),
+                    data_type: n.metric.data_type.clone(),
+                });
+                diff_series(&mut changes, &nothing, n, options);
+            }
+            (None, None) => {}
+        }
+    }
+    changes
+}
+
+/// Render changes like a unified diff of the expositions, without context lines
+pub fn render_diff(changes: &[Change]) -> String {
+    let mut res = String::new();
+    let mut line = |prefix: char, write: &dyn Fn(&mut String) -> std::fmt::Result| {
+        res.push(prefix);
+        // Writing to a String can't fail
+        let _ = write(&mut res);
+    };
+    for c in changes {
+        match c {
+            Change::FamilyAdded { name, data_type } => {
+                line('+', &|w| write_type(w, name, data_type))
+            }
+            Change::FamilyRemoved { name, data_type } => {
+                line('-', &|w| write_type(w, name, data_type))
+            }
+            Change::TypeChanged { name, old, new } => {
+                line('-', &|w| write_type(w, name, old));
+                line('+', &|w| write_type(w, name, new));
+            }
+            Change::HelpChanged { name, old, new } => {
+                if let Some(doc) = old {
+                    line('-', &|w| write_help(w, name, doc));
+                }
+                if let Some(doc) = new {
+                    line('+', &|w| write_help(w, name, doc));
+                }
+            }
+            Change::SeriesAdded { name, sample } => line('+', &|w| write_sample(w, name, sample)),
+            Change::SeriesRemoved { name, sample } => line('-', &|w| write_sample(w, name, sample)),
+            Change::ValueChanged { name, old, new } => {
+                line('-', &|w| write_sample(w, name, old));
+                line('+', &|w| write_sample(w, name, new));
+            }
+        }
+    }
+    res
+}
+
+#[test]
+fn test_diff() {
+    let old = crate::parse_complete(
+        r#"# HELP up Whether the target is up
+# TYPE up gauge
+up{instance="a"} 1
+up{instance="b"} 1
+# TYPE req counter
+req 100
+# TYPE gone gauge
+gone 1
+"#,
+    )
+    .unwrap();
+    let new = crate::parse_complete(
+        r#"# HELP up Whether the target is reachable
+# TYPE up gauge
+up{instance="a"} 1
+up{instance="c"} 0
+# TYPE req gauge
+req 100.5
+# TYPE new histogram
+new_bucket{le="+Inf"} 1
+new_sum 2
+new_count 1
+"#,
+    )
+    .unwrap();
+
+    let changes = diff(&old, &new, &DiffOptions::default());
+    assert_eq!(changes.len(), 11, "{:?}", changes);
+    assert_eq!(
+        changes[0],
+        Change::FamilyRemoved {
+            name: "gone".to_string(),
+            data_type: MetricType::Gauge
+        }
+    );
+    assert_eq!(
+        render_diff(&changes),
+        r#"-# TYPE gone gauge
+-gone 1
++# TYPE new histogram
++new_bucket{le="+Inf"} 1
++new_count 1
++new_sum 2
+-# TYPE req counter
++# TYPE req gauge
+-req 100
++req 100.5
+-# HELP up Whether the target is up
++# HELP up Whether the target is reachable
+-up{instance="b"} 1
++up{instance="c"} 0
+"#
+    );
+
+    let options = DiffOptions {
+        tolerance: 1f64,
+        ..DiffOptions::default()
+    };
+    assert!(!diff(&old, &new, &options)
+        .iter()
+        .any(|c| matches!(c, Change::ValueChanged { .. })));
+    let options = DiffOptions {
+        relative_tolerance: 0.01,
+        ..DiffOptions::default()
+    };
+    assert!(!diff(&old, &new, &options)
+        .iter()
+        .any(|c| matches!(c, Change::ValueChanged { .. })));
+    assert_eq!(diff(&new, &new, &DiffOptions::default()), vec![]);
+}
+
+#[test]
+fn test_diff_infinite() {
+    let changed = |old: &str, new: &str| {
+        let options = DiffOptions {
+            tolerance: 1f64,
+            relative_tolerance: 0.5,
+        };
+        !diff(
+            &crate::parse_complete(old).unwrap(),
+            &crate::parse_complete(new).unwrap(),
+            &options,
+        )
+        .is_empty()
+    };
+    assert!(changed("a +Inf\n", "a 5\n"));
+    assert!(changed("a 5\n", "a +Inf\n"));
+    assert!(changed("a +Inf\n", "a -Inf\n"));
+    assert!(changed("a -Inf\n", "a NaN\n"));
+    assert!(!changed("a +Inf\n", "a +Inf\n"));
+    assert!(!changed("a -Inf\n", "a -Inf\n"));
+}
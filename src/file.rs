@@ -0,0 +1,96 @@
+//! Parse files by memory mapping them so large archives don't have to be read in memory first.
+use crate::types::{Err, Metric, ParseOptions};
+use crate::{add_line, into_sorted_metrics, Limits, LinePosition};
+use memmap2::Mmap;
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+
+/// Parse a file and return a vector of metrics extracted from it.
+///
+/// # Safety
+///
+/// Same as `parse_file_with_options`.
+pub unsafe fn parse_file<P: AsRef<Path>>(path: P) -> Result<Vec<Metric>, Err> {
+    parse_file_with_options(path, &ParseOptions::default())
+}
+
+/// Parse a file with specific options and return a vector of metrics extracted from it.
+/// The file is memory mapped and each line is validated as UTF-8 and parsed in place, only the strings kept
+/// in the metrics are copied.
+///
+/// # Safety
+///
+/// The file must not be modified or truncated by this or another process until the function returns.
+/// The mapped bytes would change under the parser, which is undefined behavior, and reading past
+/// the end of a truncated file kills the process with `SIGBUS`. Use `parse_reader` for files that may change,
+/// like the ones written by exporters.
+pub unsafe fn parse_file_with_options<P: AsRef<Path>>(
+    path: P,
+    options: &ParseOptions,
+) -> Result<Vec<Metric>, Err> {
+    let file = File::open(path).map_err(Err::Io)?;
+    // Safety: the caller guarantees the file isn't modified while it's mapped, the map is dropped before returning
+    let map = unsafe { Mmap::map(&file) }.map_err(Err::Io)?;
+    Limits::default().check_body_size(map.len(), options)?;
+
+    let mut acc = HashMap::new();
+    let mut position = LinePosition::default();
+    let mut rest: &[u8] = &map;
+    while !rest.is_empty() {
+        let end = rest
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(rest.len(), |i| i + 1);
+        let (line, r) = rest.split_at(end);
+        rest = r;
+        if line.ends_with(b"\n") {
+            add_line(
+                &mut acc,
+                position.parse_terminated(line, line.len(), options)?,
+                None,
+            );
+        } else {
+            // Only an unterminated last line gets copied, to add the missing line break
+            let mut buf = line.to_vec();
+            add_line(&mut acc, position.parse(&mut buf, options)?, None);
+        }
+    }
+    Ok(into_sorted_metrics(acc))
+}
+
+#[test]
+fn test_parse_file() {
+    fn parse(path: &Path, options: &ParseOptions) -> Result<Vec<Metric>, Err> {
+        // Safety: the fixtures aren't modified by the tests
+        unsafe { parse_file_with_options(path, options) }
+    }
+    for name in &["ok_geth.prom", "ok_crlf.prom", "ok_empty_file.prom"] {
+        let path = Path::new("fixtures").join(name);
+        assert_eq!(
+            parse(&path, &ParseOptions::default()).unwrap(),
+            crate::parse_complete_bytes(&std::fs::read(&path).unwrap()).unwrap(),
+            "{}",
+            name
+        );
+    }
+    let path = |name| Path::new("fixtures").join(name);
+    assert!(matches!(
+        parse(&path("nok_invalid_utf8.prom"), &ParseOptions::default()),
+        Err(Err::InvalidUtf8 { .. })
+    ));
+    assert!(parse(
+        &path("lenient_ok_sample_no_final_endline.prom"),
+        &ParseOptions::default()
+    )
+    .is_err());
+    assert!(parse(
+        &path("lenient_ok_sample_no_final_endline.prom"),
+        &ParseOptions::lenient()
+    )
+    .is_ok());
+    assert!(matches!(
+        unsafe { parse_file("fixtures/missing.prom") },
+        Err(Err::Io(_))
+    ));
+}
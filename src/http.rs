@@ -0,0 +1,327 @@
+//! A minimal HTTP/1.1 server, just enough to expose metrics without pulling a web framework.
+//! Each connection handles a single request and is closed after the response.
+//! Requests are bounded in size and duration and connections in number so clients can't exhaust the server.
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Requests with larger bodies are rejected
+const MAX_BODY_SIZE: usize = 64 * 1024 * 1024;
+/// Request lines and header lines longer than this are rejected
+const MAX_LINE_LENGTH: usize = 8 * 1024;
+/// Requests with more headers are rejected
+const MAX_HEADERS: usize = 100;
+/// Time allowed to receive a whole request, and to send each part of the response
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// Connections handled at the same time, new ones wait to be accepted
+const MAX_CONNECTIONS: usize = 256;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub method: String,
+    /// The path without the query string
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn new(method: &str, path: &str) -> Self {
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// The value of a header, names are case insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &str, body: Vec<u8>) -> Self {
+        Response {
+            status,
+            headers: vec![("Content-Type".to_string(), content_type.to_string())],
+            body,
+        }
+    }
+
+    /// A plain text response, mostly used for errors
+    pub fn text(status: u16, body: &str) -> Self {
+        Response::new(
+            status,
+            "text/plain; charset=utf-8",
+            body.as_bytes().to_vec(),
+        )
+    }
+
+    /// The value of a header, names are case insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        500 => "Internal Server Error",
+        _ => "",
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Read a line without buffering more than `MAX_LINE_LENGTH` bytes
+fn read_line<R: BufRead>(reader: &mut R, line: &mut String) -> io::Result<usize> {
+    let n = reader.take(MAX_LINE_LENGTH as u64 + 1).read_line(line)?;
+    if n > MAX_LINE_LENGTH {
+        return Err(invalid("line too long"));
+    }
+    Ok(n)
+}
+
+/// Read a request, returns `None` if the connection was closed before sending anything
+pub fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Option<Request>> {
+    let mut line = String::new();
+    if read_line(reader, &mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(m), Some(t)) => (m, t),
+        _ => return Err(invalid("malformed request line")),
+    };
+    let mut request = Request::new(method, target.split('?').next().unwrap_or(""));
+    loop {
+        let mut line = String::new();
+        read_line(reader, &mut line)?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if request.headers.len() == MAX_HEADERS {
+            return Err(invalid("too many headers"));
+        }
+        let (k, v) = line
+            .split_once(':')
+            .ok_or_else(|| invalid("malformed header"))?;
+        request
+            .headers
+            .push((k.trim().to_string(), v.trim().to_string()));
+    }
+    let length: usize = match request.header("Content-Length") {
+        Some(l) => l.parse().map_err(|_| invalid("malformed Content-Length"))?,
+        None => 0,
+    };
+    if length > MAX_BODY_SIZE {
+        return Err(invalid("body too large"));
+    }
+    // The body is read as it's received instead of trusting the length to allocate it
+    if reader.take(length as u64).read_to_end(&mut request.body)? < length {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(Some(request))
+}
+
+/// Write the status line and headers of a response, `Content-Length` is the length of its body
+fn write_head<W: Write>(w: &mut W, response: &Response) -> io::Result<()> {
+    write!(
+        w,
+        "HTTP/1.1 {} {}\r\n",
+        response.status,
+        reason(response.status)
+    )?;
+    for (k, v) in &response.headers {
+        write!(w, "{}: {}\r\n", k, v)?;
+    }
+    write!(
+        w,
+        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+        response.body.len()
+    )
+}
+
+/// Write a response and its body, the connection is closed afterwards
+pub fn write_response<W: Write>(w: &mut W, response: &Response) -> io::Result<()> {
+    write_head(w, response)?;
+    w.write_all(&response.body)?;
+    w.flush()
+}
+
+/// A stream whose reads fail once the deadline is past, a slow client can't keep a connection open by trickling bytes
+struct DeadlineStream {
+    stream: TcpStream,
+    deadline: Instant,
+}
+
+impl Read for DeadlineStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self
+            .deadline
+            .checked_duration_since(Instant::now())
+            .filter(|d| !d.is_zero())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::TimedOut, "request timed out"))?;
+        self.stream.set_read_timeout(Some(remaining))?;
+        self.stream.read(buf)
+    }
+}
+
+fn handle_connection<H: Fn(&Request) -> Response>(
+    stream: TcpStream,
+    handler: &H,
+) -> io::Result<()> {
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut reader = BufReader::new(DeadlineStream {
+        stream,
+        deadline: Instant::now() + TIMEOUT,
+    });
+    let (response, head) = match read_request(&mut reader) {
+        Ok(Some(request)) => (handler(&request), request.method == "HEAD"),
+        Ok(None) => return Ok(()),
+        Err(e) => (Response::text(400, &e.to_string()), false),
+    };
+    let stream = &mut reader.get_mut().stream;
+    // The answer to a HEAD request has the headers of the full response but no body
+    if head {
+        write_head(stream, &response)?;
+        return stream.flush();
+    }
+    write_response(stream, &response)
+}
+
+/// Counts the connections being handled, up to `MAX_CONNECTIONS`
+#[derive(Default)]
+struct Connections {
+    count: Mutex<usize>,
+    released: Condvar,
+}
+
+/// A connection being handled, it's released when dropped
+struct Slot(Arc<Connections>);
+
+impl Connections {
+    /// Wait for a connection to be released if there are too many
+    fn acquire(self: &Arc<Self>) -> Slot {
+        let count = self.count.lock().unwrap_or_else(|e| e.into_inner());
+        let mut count = self
+            .released
+            .wait_while(count, |c| *c >= MAX_CONNECTIONS)
+            .unwrap_or_else(|e| e.into_inner());
+        *count += 1;
+        Slot(self.clone())
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        *self.0.count.lock().unwrap_or_else(|e| e.into_inner()) -= 1;
+        self.0.released.notify_one();
+    }
+}
+
+/// Accept connections forever and answer each request with `handler`, every connection is handled in its own thread.
+/// At most `MAX_CONNECTIONS` are handled at the same time, the next ones wait in the listen backlog.
+pub fn serve<H>(listener: TcpListener, handler: H) -> io::Result<()>
+where
+    H: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+    let handler = Arc::new(handler);
+    let connections = Arc::new(Connections::default());
+    loop {
+        let slot = connections.acquire();
+        let (stream, _) = listener.accept()?;
+        let handler = handler.clone();
+        thread::spawn(move || {
+            // There's no one to report the error to, the client sees the connection closing
+            let _ = handle_connection(stream, handler.as_ref());
+            drop(slot);
+        });
+    }
+}
+
+#[test]
+fn test_read_request() {
+    let mut input = &b"PUT /metrics/job/foo?x=y HTTP/1.1\r\nHost: localhost\r\ncontent-length: 6\r\n\r\nfoo 1\n"[..];
+    let request = read_request(&mut input).unwrap().unwrap();
+    assert_eq!(request.method, "PUT");
+    assert_eq!(request.path, "/metrics/job/foo");
+    assert_eq!(request.header("Content-Length"), Some("6"));
+    assert_eq!(request.body, b"foo 1\n");
+    assert_eq!(read_request(&mut &b""[..]).unwrap(), None);
+    assert!(read_request(&mut &b"GET\r\n\r\n"[..]).is_err());
+}
+
+#[test]
+fn test_read_request_limits() {
+    let read = |s: String| read_request(&mut s.as_bytes());
+    let long = "a".repeat(MAX_LINE_LENGTH);
+    assert!(read(format!("GET /{} HTTP/1.1\r\n\r\n", long)).is_err());
+    assert!(read(format!("GET / HTTP/1.1\r\nX: {}\r\n\r\n", long)).is_err());
+    let headers = "X: y\r\n".repeat(MAX_HEADERS);
+    assert!(read(format!("GET / HTTP/1.1\r\n{}\r\n", headers)).is_ok());
+    assert!(read(format!("GET / HTTP/1.1\r\n{}X: y\r\n\r\n", headers)).is_err());
+    // The announced length isn't allocated up front
+    let res = read(format!(
+        "PUT / HTTP/1.1\r\nContent-Length: {}\r\n\r\nfoo",
+        MAX_BODY_SIZE
+    ));
+    assert_eq!(res.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    assert!(read(format!(
+        "PUT / HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+        MAX_BODY_SIZE + 1
+    ))
+    .is_err());
+}
+
+#[test]
+fn test_connections() {
+    let connections = Arc::new(Connections::default());
+    let slots: Vec<Slot> = (0..MAX_CONNECTIONS)
+        .map(|_| connections.acquire())
+        .collect();
+    let waiting = {
+        let connections = connections.clone();
+        thread::spawn(move || drop(connections.acquire()))
+    };
+    thread::sleep(Duration::from_millis(50));
+    assert!(!waiting.is_finished());
+    drop(slots);
+    waiting.join().unwrap();
+    assert_eq!(*connections.count.lock().unwrap(), 0);
+}
+
+#[test]
+fn test_write_response() {
+    let mut out = Vec::new();
+    write_response(&mut out, &Response::text(404, "not found")).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "HTTP/1.1 404 Not Found\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: 9\r\nConnection: close\r\n\r\nnot found"
+    );
+}
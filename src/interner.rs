@@ -0,0 +1,146 @@
+//! Share the strings of metric names, label names and label values between parses of similar inputs,
+//! like the successive scrapes of a target.
+use crate::labels::Labels;
+use crate::samples::SampleEntry;
+use crate::types::{Err, Metric, MetricType, ParseOptions, Sample};
+use crate::{parse_lines, parse_str};
+use std::collections::HashSet;
+use std::io::BufRead;
+use std::sync::Arc;
+
+/// The number of strings kept by `Interner::default`
+pub const DEFAULT_CAPACITY: usize = 1 << 16;
+
+/// A set of shared strings with a bounded size.
+/// Strings are kept in two generations, when the current one is full it becomes the previous one and the older strings are dropped.
+/// Strings used since the last rotation are moved back to the current generation so the ones still in use survive.
+#[derive(Debug)]
+pub struct Interner {
+    current: HashSet<Arc<str>>,
+    previous: HashSet<Arc<str>>,
+    capacity: usize,
+}
+
+impl Default for Interner {
+    fn default() -> Self {
+        Interner::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl Interner {
+    /// An interner keeping at most about `2 * capacity` strings
+    pub fn new(capacity: usize) -> Self {
+        Interner {
+            current: HashSet::new(),
+            previous: HashSet::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// The number of strings kept
+    pub fn len(&self) -> usize {
+        self.current.len() + self.previous.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the shared copy of a string, creating it if needed
+    pub fn intern(&mut self, s: &str) -> Arc<str> {
+        if let Some(res) = self.current.get(s) {
+            return res.clone();
+        }
+        let res = self.previous.take(s).unwrap_or_else(|| Arc::from(s));
+        if self.current.len() >= self.capacity {
+            self.previous = std::mem::take(&mut self.current);
+        }
+        self.current.insert(res.clone());
+        res
+    }
+
+    /// A metric whose name is shared
+    pub(crate) fn metric(&mut self, name: &str, t: MetricType) -> Metric {
+        Metric {
+            name: self.intern(name),
+            data_type: t,
+            help: None,
+            samples: Vec::new(),
+        }
+    }
+
+    /// A sample whose label names and values are shared, they're interned from the input without copying them first
+    pub(crate) fn sample(&mut self, s: SampleEntry) -> Sample {
+        let labels: Labels = s
+            .labels
+            .into_iter()
+            .map(|(k, v)| (self.intern(&k), self.intern(&v)))
+            .collect();
+        Sample {
+            labels,
+            value: s.value,
+            timestamp: s.timestamp_ms,
+        }
+    }
+}
+
+/// A parser reusing the metric names, label names and label values of previous parses instead of allocating them again.
+#[derive(Debug, Default)]
+pub struct Parser {
+    pub options: ParseOptions,
+    pub interner: Interner,
+}
+
+impl Parser {
+    pub fn new(options: ParseOptions, interner: Interner) -> Self {
+        Parser { options, interner }
+    }
+
+    /// Same as `parse_complete_with_options`
+    pub fn parse(&mut self, input: &str) -> Result<Vec<Metric>, Err> {
+        parse_str(input, &self.options, Some(&mut self.interner))
+    }
+
+    /// Same as `parse_complete_bytes_with_options`
+    pub fn parse_bytes(&mut self, input: &[u8]) -> Result<Vec<Metric>, Err> {
+        let s = std::str::from_utf8(input).map_err(|e| Err::invalid_utf8(input, e))?;
+        self.parse(s)
+    }
+
+    /// Same as `parse_reader_with_options`
+    pub fn parse_reader<R: BufRead>(&mut self, reader: R) -> Result<Vec<Metric>, Err> {
+        parse_lines(reader, &self.options, Some(&mut self.interner))
+    }
+}
+
+#[test]
+fn test_intern() {
+    let mut interner = Interner::new(2);
+    let a = interner.intern("a");
+    assert!(Arc::ptr_eq(&a, &interner.intern("a")));
+    interner.intern("b");
+    // Rotates, `a` and `b` are still there
+    interner.intern("c");
+    assert_eq!(interner.len(), 3);
+    assert!(Arc::ptr_eq(&a, &interner.intern("a")));
+    // `b` wasn't used since the rotation so it's dropped
+    interner.intern("d");
+    assert_eq!(interner.len(), 3);
+    assert!(!interner.previous.contains("b"));
+}
+
+#[test]
+fn test_parser() {
+    let input = "# TYPE foo counter\nfoo{a=\"b\"} 1\nfoo{a=\"c\"} 2\nbar{a=\"b\"} 3\n";
+    let mut parser = Parser::default();
+    let first = parser.parse(input).unwrap();
+    assert_eq!(first, crate::parse_complete(input).unwrap());
+    let second = parser.parse_reader(input.as_bytes()).unwrap();
+    assert_eq!(first, second);
+    // `foo`, `bar`, `a`, `b` and `c`
+    assert_eq!(parser.interner.len(), 5);
+
+    let name = |m: &Metric, i: usize| m.samples[i].labels.iter().next().unwrap().0.as_ptr();
+    assert_eq!(name(&first[0], 0), name(&second[1], 1));
+    assert!(Arc::ptr_eq(&first[1].name, &second[1].name));
+}
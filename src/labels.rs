@@ -0,0 +1,229 @@
+//! The labels of a sample, kept sorted by name like Prometheus' `labels.Labels`.
+use std::fmt;
+use std::iter::FromIterator;
+use std::ops::Index;
+use std::sync::Arc;
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+/// Separates names and values when hashing, it can't appear in valid UTF-8
+const SEPARATOR: u8 = 0xff;
+
+/// A set of labels sorted by name, names are unique.
+/// Two samples have the same labels if and only if their `Labels` are equal, which makes it usable as a series identity.
+/// Names and values are shared strings so labels produced with an `Interner` don't copy them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Labels(Vec<(Arc<str>, Arc<str>)>);
+
+impl Labels {
+    pub fn new() -> Self {
+        Labels(Vec::new())
+    }
+
+    /// Build labels from name and value pairs, when a name appears several times the last value wins
+    pub fn from_pairs(labels: &[(&str, &str)]) -> Self {
+        labels
+            .iter()
+            .map(|&(k, v)| (Arc::from(k), Arc::from(v)))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The labels sorted by name
+    pub fn iter(&self) -> Iter<'_> {
+        Iter(self.0.iter())
+    }
+
+    fn position(&self, name: &str) -> Result<usize, usize> {
+        self.0.binary_search_by(|(k, _)| (**k).cmp(name))
+    }
+
+    /// The value of a label
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.position(name).ok().map(|i| &*self.0[i].1)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.position(name).is_ok()
+    }
+
+    /// Set a label and return its previous value
+    pub fn insert(&mut self, name: &str, value: &str) -> Option<String> {
+        match self.position(name) {
+            Ok(i) => Some(std::mem::replace(&mut self.0[i].1, Arc::from(value)).to_string()),
+            Err(i) => {
+                self.0.insert(i, (Arc::from(name), Arc::from(value)));
+                None
+            }
+        }
+    }
+
+    /// Remove a label and return its value
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        self.position(name)
+            .ok()
+            .map(|i| self.0.remove(i).1.to_string())
+    }
+
+    /// A copy of these labels with another label set
+    pub fn with(&self, name: &str, value: &str) -> Labels {
+        let mut res = self.clone();
+        res.insert(name, value);
+        res
+    }
+
+    /// A copy of these labels without the labels named in `names`
+    pub fn without(&self, names: &[&str]) -> Labels {
+        Labels(
+            self.0
+                .iter()
+                .filter(|(k, _)| !names.contains(&&**k))
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// A stable hash of the labels, the same labels always get the same fingerprint across processes and versions.
+    /// This is FNV-1a over the names and values separated by 0xff like the input of Prometheus' `labels.Hash`.
+    pub fn fingerprint(&self) -> u64 {
+        let mut h = FNV_OFFSET;
+        let mut write = |bytes: &[u8]| {
+            for &b in bytes {
+                h ^= u64::from(b);
+                h = h.wrapping_mul(FNV_PRIME);
+            }
+        };
+        for (k, v) in &self.0 {
+            write(k.as_bytes());
+            write(&[SEPARATOR]);
+            write(v.as_bytes());
+            write(&[SEPARATOR]);
+        }
+        h
+    }
+}
+
+impl FromIterator<(Arc<str>, Arc<str>)> for Labels {
+    /// When a name appears several times, the last value wins
+    fn from_iter<I: IntoIterator<Item = (Arc<str>, Arc<str>)>>(iter: I) -> Self {
+        let mut res: Vec<(Arc<str>, Arc<str>)> = iter.into_iter().collect();
+        // The sort is stable so after reversing, the last value of a name comes first and is the one kept by dedup
+        res.reverse();
+        res.sort_by(|a, b| a.0.cmp(&b.0));
+        res.dedup_by(|a, b| a.0 == b.0);
+        Labels(res)
+    }
+}
+
+impl FromIterator<(String, String)> for Labels {
+    /// When a name appears several times, the last value wins
+    fn from_iter<I: IntoIterator<Item = (String, String)>>(iter: I) -> Self {
+        iter.into_iter()
+            .map(|(k, v)| (Arc::from(k), Arc::from(v)))
+            .collect()
+    }
+}
+
+/// An iterator over the names and values of `Labels`, sorted by name
+#[derive(Debug, Clone)]
+pub struct Iter<'a>(std::slice::Iter<'a, (Arc<str>, Arc<str>)>);
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a str, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(k, v)| (&**k, &**v))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|(k, v)| (&**k, &**v))
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+impl<'a> IntoIterator for &'a Labels {
+    type Item = (&'a str, &'a str);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl Index<&str> for Labels {
+    type Output = str;
+
+    /// Panics if the label is missing
+    fn index(&self, name: &str) -> &str {
+        self.get(name)
+            .unwrap_or_else(|| panic!("missing label {}", name))
+    }
+}
+
+impl fmt::Display for Labels {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("{")?;
+        for (i, (k, v)) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}={:?}", k, v)?;
+        }
+        f.write_str("}")
+    }
+}
+
+#[test]
+fn test_labels() {
+    let mut labels = Labels::from_pairs(&[("b", "2"), ("a", "1"), ("b", "3")]);
+    assert_eq!(
+        labels.iter().collect::<Vec<_>>(),
+        vec![("a", "1"), ("b", "3")]
+    );
+    assert_eq!(labels.get("a"), Some("1"));
+    assert_eq!(labels.get("c"), None);
+    assert_eq!(&labels["b"], "3");
+    assert_eq!(labels.insert("c", "4"), None);
+    assert_eq!(labels.insert("a", "5"), Some("1".to_string()));
+    assert_eq!(labels.remove("b"), Some("3".to_string()));
+    assert_eq!(labels, Labels::from_pairs(&[("c", "4"), ("a", "5")]));
+    assert_eq!(labels.to_string(), "{a=\"5\", c=\"4\"}");
+    assert_eq!(labels.iter().len(), 2);
+    assert_eq!(labels.iter().next_back(), Some(("c", "4")));
+
+    assert_eq!(
+        labels.with("b", "6").without(&["a"]),
+        Labels::from_pairs(&[("b", "6"), ("c", "4")])
+    );
+    assert!(Labels::from_pairs(&[("a", "1")]) < Labels::from_pairs(&[("a", "2")]));
+}
+
+#[test]
+fn test_fingerprint() {
+    assert_eq!(Labels::new().fingerprint(), FNV_OFFSET);
+    let labels = Labels::from_pairs(&[("a", "1"), ("b", "2")]);
+    assert_eq!(
+        labels.fingerprint(),
+        Labels::from_pairs(&[("b", "2"), ("a", "1")]).fingerprint()
+    );
+    // The separator keeps moving characters between names and values from colliding
+    assert_ne!(
+        Labels::from_pairs(&[("ab", "c")]).fingerprint(),
+        Labels::from_pairs(&[("a", "bc")]).fingerprint()
+    );
+    assert_ne!(labels.fingerprint(), labels.with("c", "3").fingerprint());
+}
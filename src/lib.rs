@@ -1,17 +1,55 @@
-use crate::comment::{comment_parser, CommentType};
+use crate::comment::{comment_parser, unescape_help, CommentType};
 use crate::common::empty_line_parser;
+use crate::interner::Interner;
 use crate::samples::{parse_sample, SampleEntry};
-use crate::types::{Err, Metric, MetricType, Sample};
+use crate::types::{Err, Limit, Line, Metric, MetricType, ParseOptions, Sample};
 use nom::branch::alt;
 use nom::combinator::map;
 use nom::IResult;
 use std::collections::HashMap;
+use std::io::BufRead;
+use std::sync::Arc;
 
 // Restrict this to internal visibility only
 pub(crate) mod comment;
 pub(crate) mod common;
 pub(crate) mod samples;
+
+pub mod cardinality;
+#[cfg(feature = "codec")]
+pub mod codec;
+pub mod compression;
+pub mod diff;
+#[cfg(feature = "mmap")]
+pub mod file;
+#[cfg(feature = "server")]
+pub mod http;
+pub mod interner;
+pub mod labels;
+pub mod merge;
+#[cfg(feature = "rayon")]
+pub mod parallel;
+pub mod protobuf;
+#[cfg(feature = "pushgateway")]
+pub mod pushgateway;
+pub mod render;
+#[cfg(feature = "scrape")]
+pub mod scrape;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "tokio")]
+pub mod stream;
+pub mod target;
+pub mod textfile;
 pub mod types;
+pub mod visitor;
+
+/// The internal parsers, only exposed to the fuzz targets which build with `--cfg fuzzing`
+#[cfg(fuzzing)]
+pub mod fuzzing {
+    pub use crate::comment::comment_parser;
+    pub use crate::samples::{labels_parser, parse_sample, value_parser};
+}
 
 #[derive(Debug)]
 enum LineType<'a> {
@@ -20,21 +58,22 @@ enum LineType<'a> {
     Comment(CommentType<'a>),
 }
 
-fn parse_line(input: &str) -> IResult<&str, LineType> {
+fn parse_line(input: &str) -> IResult<&str, LineType<'_>> {
     alt((
-        map(comment_parser, |l| LineType::Comment(l)),
-        map(parse_sample, |l| LineType::Sample(l)),
+        map(comment_parser, LineType::Comment),
+        map(parse_sample, LineType::Sample),
         map(empty_line_parser, |_| LineType::Empty),
     ))(input)
 }
 
+/// The lines of an input, an error holds the input from the start of the line that can't be parsed
 struct InputIter<'a>(&'a str);
 
 impl<'a> Iterator for InputIter<'a> {
-    type Item = Result<LineType<'a>, Err>;
+    type Item = Result<LineType<'a>, &'a str>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.0.len() == 0 {
+        if self.0.is_empty() {
             None
         } else {
             match parse_line(self.0) {
@@ -42,91 +81,331 @@ impl<'a> Iterator for InputIter<'a> {
                     self.0 = res.0;
                     Some(Ok(res.1))
                 }
-                Result::Err(err) => Some(Result::Err(Err::from(err))),
+                Result::Err(_) => Some(Result::Err(self.0)),
             }
         }
     }
 }
 
-impl<'a> Into<Metric> for SampleEntry<'a> {
-    fn into(self) -> Metric {
+impl<'a> From<SampleEntry<'a>> for Metric {
+    fn from(s: SampleEntry<'a>) -> Metric {
         Metric {
-            name: self.name.to_string(),
+            name: Arc::from(&*s.name),
             data_type: MetricType::Untyped,
-            samples: vec![self.into()],
+            help: None,
+            samples: vec![s.into()],
         }
     }
 }
 
-impl<'a> Into<Sample> for SampleEntry<'a> {
-    fn into(self) -> Sample {
+impl<'a> From<SampleEntry<'a>> for Sample {
+    fn from(s: SampleEntry<'a>) -> Sample {
         Sample {
-            labels: self
+            labels: s
                 .labels
-                .iter()
-                .map(|(&k, v)| (k.to_string(), v.to_string()))
+                .into_iter()
+                .map(|(k, v)| (Arc::from(k), Arc::from(v)))
                 .collect(),
-            value: self.value,
-            timestamp: self.timestamp_ms,
+            value: s.value,
+            timestamp: s.timestamp_ms,
         }
     }
 }
 
-impl Metric {
-    fn append_sample_entry(&mut self, s: SampleEntry) {
-        assert_eq!(
-            s.name, self.name,
-            "Names should be equal when calling update on a metric"
-        );
-        self.push_sample(s.into());
+impl<'a> From<LineType<'a>> for Line {
+    fn from(l: LineType<'a>) -> Line {
+        match l {
+            LineType::Empty => Line::Empty,
+            LineType::Sample(s) => Line::Sample {
+                name: s.name.to_string(),
+                sample: s.into(),
+            },
+            LineType::Comment(CommentType::Type(name, data_type)) => Line::Type {
+                name: name.to_string(),
+                data_type,
+            },
+            LineType::Comment(CommentType::Help(name, doc)) => Line::Help {
+                name: name.to_string(),
+                doc: unescape_help(doc),
+            },
+            LineType::Comment(CommentType::Other(s)) => Line::Comment(s.to_string()),
+        }
     }
-    fn append_type_def(&mut self, s: &str, t: MetricType) {
-        assert_eq!(
-            s,
-            &self.name[..],
-            "Names should be equal when calling update on a metric"
-        );
+}
+
+impl Metric {
+    fn append_type_def(&mut self, t: MetricType) {
         self.data_type = t;
     }
+    fn append_help(&mut self, doc: &str) {
+        self.help = Some(unescape_help(doc));
+    }
 }
 
-fn add_comment<'a, 'b>(map: &mut HashMap<&'a str, Metric>, c: CommentType<'a>) {
-    if let CommentType::Type(s, t) = c {
-        if let Some(x) = map.get_mut(s) {
-            x.append_type_def(s, t);
-        } else {
-            map.insert(s, Metric::new(s, t));
+/// The metrics being built, keyed by a shared copy of their name
+type MetricMap = HashMap<Arc<str>, Metric>;
+
+fn new_metric(name: &str, t: MetricType, interner: Option<&mut Interner>) -> Metric {
+    match interner {
+        Some(i) => i.metric(name, t),
+        None => Metric::new(name, t),
+    }
+}
+
+fn insert_metric(map: &mut MetricMap, m: Metric) {
+    map.insert(m.name.clone(), m);
+}
+
+fn add_comment(map: &mut MetricMap, c: CommentType, interner: Option<&mut Interner>) {
+    match c {
+        CommentType::Type(s, t) => {
+            if let Some(x) = map.get_mut(&*s) {
+                x.append_type_def(t);
+            } else {
+                insert_metric(map, new_metric(&s, t, interner));
+            }
         }
+        CommentType::Help(s, doc) => {
+            if let Some(x) = map.get_mut(&*s) {
+                x.append_help(doc);
+            } else {
+                let mut m = new_metric(&s, MetricType::Untyped, interner);
+                m.append_help(doc);
+                insert_metric(map, m);
+            }
+        }
+        CommentType::Other(_) => {}
+    }
+}
+
+fn add_sample(map: &mut MetricMap, s: SampleEntry, interner: Option<&mut Interner>) {
+    let name = s.name.clone();
+    match (map.get_mut(&*name), interner) {
+        (Some(x), Some(i)) => x.push_sample(i.sample(s)),
+        (Some(x), None) => x.push_sample(s.into()),
+        (None, Some(i)) => {
+            let mut m = i.metric(&name, MetricType::Untyped);
+            m.push_sample(i.sample(s));
+            insert_metric(map, m);
+        }
+        (None, None) => insert_metric(map, s.into()),
     }
 }
 
-fn add_sample<'a, 'b>(map: &'b mut HashMap<&'a str, Metric>, s: SampleEntry<'a>) {
-    if let Some(x) = map.get_mut(s.name) {
-        x.append_sample_entry(s);
-    } else {
-        map.insert(s.name, s.into());
+fn add_line(map: &mut MetricMap, l: LineType, interner: Option<&mut Interner>) {
+    match l {
+        LineType::Comment(c) => add_comment(map, c, interner),
+        LineType::Sample(s) => add_sample(map, s, interner),
+        LineType::Empty => {}
     };
 }
 
+fn into_sorted_metrics(mut map: MetricMap) -> Vec<Metric> {
+    let mut res: Vec<Metric> = map.drain().map(|(_, v)| v).collect();
+    // Make the order constant
+    res.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+    res
+}
+
+/// Split the input before its last line if it's not terminated by a line break and the options allow it.
+fn split_unterminated<'a>(input: &'a str, options: &ParseOptions) -> (&'a str, Option<&'a str>) {
+    if !options.allow_missing_final_newline || input.is_empty() || input.ends_with('\n') {
+        return (input, None);
+    }
+    let idx = input.rfind('\n').map_or(0, |i| i + 1);
+    (&input[..idx], Some(&input[idx..]))
+}
+
 /// Parse a string and return a vector of metrics extracted from it.
-pub fn parse_complete<'a>(input: &'a str) -> Result<Vec<Metric>, Err> {
-    let mut acc: HashMap<&'a str, Metric> = HashMap::new();
-    for l in InputIter(input) {
-        match l? {
-            LineType::Comment(c) => add_comment(&mut acc, c),
-            LineType::Sample(s) => add_sample(&mut acc, s),
-            LineType::Empty => {}
+pub fn parse_complete(input: &str) -> Result<Vec<Metric>, Err> {
+    parse_complete_with_options(input, &ParseOptions::default())
+}
+
+/// Parse a string with specific options and return a vector of metrics extracted from it.
+pub fn parse_complete_with_options(
+    input: &str,
+    options: &ParseOptions,
+) -> Result<Vec<Metric>, Err> {
+    parse_str(input, options, None)
+}
+
+fn parse_str(
+    input: &str,
+    options: &ParseOptions,
+    mut interner: Option<&mut Interner>,
+) -> Result<Vec<Metric>, Err> {
+    let mut limits = Limits::default();
+    limits.check_body_size(input.len(), options)?;
+    let (input, last_line) = split_unterminated(input, options);
+    // Only the unterminated line gets copied to add the missing line break
+    let last_line = last_line.map(|l| format!("{}\n", l));
+    let mut acc = HashMap::new();
+    for l in InputIter(input).chain(last_line.iter().flat_map(|l| InputIter(l))) {
+        let l = l.map_err(|rest| Err::parse(limits.line + 1, rest))?;
+        limits.check_line(&l, options)?;
+        add_line(&mut acc, l, interner.as_deref_mut());
+    }
+    Ok(into_sorted_metrics(acc))
+}
+
+/// Parse a byte slice and return a vector of metrics extracted from it.
+/// The input must be valid UTF-8, the position of the first invalid byte is reported otherwise.
+pub fn parse_complete_bytes(input: &[u8]) -> Result<Vec<Metric>, Err> {
+    parse_complete_bytes_with_options(input, &ParseOptions::default())
+}
+
+/// Parse a byte slice with specific options and return a vector of metrics extracted from it.
+pub fn parse_complete_bytes_with_options(
+    input: &[u8],
+    options: &ParseOptions,
+) -> Result<Vec<Metric>, Err> {
+    let s = std::str::from_utf8(input).map_err(|e| Err::invalid_utf8(input, e))?;
+    parse_complete_with_options(s, options)
+}
+
+/// Parse a reader line by line and return a vector of metrics extracted from it.
+/// Only one line is kept in memory at a time so this works on large or compressed inputs.
+pub fn parse_reader<R: BufRead>(reader: R) -> Result<Vec<Metric>, Err> {
+    parse_reader_with_options(reader, &ParseOptions::default())
+}
+
+/// Parse a reader line by line with specific options and return a vector of metrics extracted from it.
+pub fn parse_reader_with_options<R: BufRead>(
+    reader: R,
+    options: &ParseOptions,
+) -> Result<Vec<Metric>, Err> {
+    parse_lines(reader, options, None)
+}
+
+fn parse_lines<R: BufRead>(
+    reader: R,
+    options: &ParseOptions,
+    mut interner: Option<&mut Interner>,
+) -> Result<Vec<Metric>, Err> {
+    // A line is never buffered further than the body size limit, one byte more is enough to see it's exceeded
+    let mut reader = reader.take(
+        options
+            .body_size_limit
+            .map_or(u64::MAX, |l| (l as u64).saturating_add(1)),
+    );
+    let mut acc = HashMap::new();
+    let mut buf = Vec::new();
+    let mut position = LinePosition::default();
+    loop {
+        buf.clear();
+        if reader.read_until(b'\n', &mut buf).map_err(Err::Io)? == 0 {
+            break;
+        }
+        add_line(
+            &mut acc,
+            position.parse(&mut buf, options)?,
+            interner.as_deref_mut(),
+        );
+    }
+    Ok(into_sorted_metrics(acc))
+}
+
+/// Enforces the limits of `ParseOptions` on lines as they're parsed
+#[derive(Default)]
+struct Limits {
+    line: usize,
+    samples: usize,
+}
+
+impl Limits {
+    fn exceeded(&self, limit: Limit) -> Err {
+        Err::LimitExceeded {
+            limit,
+            line: self.line,
+        }
+    }
+
+    fn check_body_size(&self, size: usize, options: &ParseOptions) -> Result<(), Err> {
+        match options.body_size_limit {
+            Some(l) if size > l => Err(self.exceeded(Limit::BodySize(l))),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_line(&mut self, l: &LineType, options: &ParseOptions) -> Result<(), Err> {
+        self.line += 1;
+        let s = match l {
+            LineType::Sample(s) => s,
+            _ => return Ok(()),
         };
+        self.samples += 1;
+        let over = |limit: Option<usize>, v: usize| limit.filter(|&l| v > l);
+        if let Some(l) = over(options.sample_limit, self.samples) {
+            return Err(self.exceeded(Limit::Samples(l)));
+        }
+        if let Some(l) = over(options.label_limit, s.labels.into_iter().count()) {
+            return Err(self.exceeded(Limit::Labels(l)));
+        }
+        if options.label_name_length_limit.is_none() && options.label_value_length_limit.is_none() {
+            return Ok(());
+        }
+        for (k, v) in s.labels {
+            if let Some(l) = over(options.label_name_length_limit, k.len()) {
+                return Err(self.exceeded(Limit::LabelNameLength(l)));
+            }
+            if let Some(l) = over(options.label_value_length_limit, v.len()) {
+                return Err(self.exceeded(Limit::LabelValueLength(l)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Keeps track of where we are in an input that is read one line at a time
+#[derive(Default)]
+struct LinePosition {
+    line: usize,
+    offset: usize,
+    limits: Limits,
+}
+
+impl LinePosition {
+    /// Parse a line read from a stream, `buf` must contain exactly one line including its line break
+    fn parse<'a>(
+        &mut self,
+        buf: &'a mut Vec<u8>,
+        options: &ParseOptions,
+    ) -> Result<LineType<'a>, Err> {
+        let len = buf.len();
+        if options.allow_missing_final_newline && buf.last() != Some(&b'\n') {
+            buf.push(b'\n');
+        }
+        self.parse_terminated(buf, len, options)
+    }
+
+    /// Parse a line ending with a line break, `len` is its length in the input
+    fn parse_terminated<'a>(
+        &mut self,
+        line: &'a [u8],
+        len: usize,
+        options: &ParseOptions,
+    ) -> Result<LineType<'a>, Err> {
+        let offset = self.offset;
+        self.line += 1;
+        self.offset += len;
+        // Checked first as a line cut by the limit can't be parsed
+        if let Some(l) = options.body_size_limit.filter(|&l| self.offset > l) {
+            return Err(Err::LimitExceeded {
+                limit: Limit::BodySize(l),
+                line: self.line,
+            });
+        }
+        let line = std::str::from_utf8(line)
+            .map_err(|e| Err::invalid_utf8_in_line(self.line, offset, e))?;
+        let l = parse_line(line).map_err(|_| Err::parse(self.line, line))?.1;
+        self.limits.check_line(&l, options)?;
+        Ok(l)
     }
-    let mut res: Vec<Metric> = acc.drain().map(|(_, v)| v).collect();
-    // Make the order constant
-    res.sort_unstable_by(|a, b| a.name.cmp(&b.name));
-    Ok(res)
 }
 
 #[cfg(test)]
 fn assert_metric(m: &Metric, name: &str, tpe: MetricType, samples: Vec<Sample>) {
-    assert_eq!(m.name, name, "name {:?}", m);
+    assert_eq!(&*m.name, name, "name {:?}", m);
     assert_eq!(m.data_type, tpe, "type {:?}", m);
     assert_eq!(m.samples, samples);
 }
@@ -173,6 +452,11 @@ rpc_duration_seconds_count 2693
     )
     .unwrap();
     assert_eq!(res.len(), 2);
+    assert_eq!(
+        res[0].help.as_deref(),
+        Some("The total number of HTTP requests.")
+    );
+    assert_eq!(res[1].help, None);
     assert_metric(
         &res[0],
         "http_requests_total",
@@ -197,3 +481,220 @@ rpc_duration_seconds_count 2693
         vec![Sample::new(2693f64, None, vec![])],
     );
 }
+
+#[test]
+fn test_parse_complete_crlf() {
+    let res = parse_complete(
+        "# HELP http_requests_total The total number of HTTP requests.\r\n\
+         # TYPE http_requests_total counter\r\n\
+         http_requests_total{code=\"200\"} 1027 1395066363000\r\n\
+         \r\n\
+         rpc_duration_seconds_count 2693\r\n",
+    )
+    .unwrap();
+    assert_eq!(res.len(), 2);
+    assert_metric(
+        &res[0],
+        "http_requests_total",
+        MetricType::Counter,
+        vec![Sample::new(
+            1027f64,
+            Some(1395066363000),
+            vec!["code", "200"],
+        )],
+    );
+    assert_metric(
+        &res[1],
+        "rpc_duration_seconds_count",
+        MetricType::Untyped,
+        vec![Sample::new(2693f64, None, vec![])],
+    );
+}
+
+#[test]
+fn test_parse_complete_bytes() {
+    let res = parse_complete_bytes(b"# TYPE foo gauge\nfoo 1\n").unwrap();
+    assert_eq!(res.len(), 1);
+    assert_metric(
+        &res[0],
+        "foo",
+        MetricType::Gauge,
+        vec![Sample::new(1f64, None, vec![])],
+    );
+
+    match parse_complete_bytes(b"foo 1\nbar{a=\"\xff\"} 2\n") {
+        Result::Err(Err::InvalidUtf8 {
+            line,
+            column,
+            offset,
+        }) => assert_eq!((line, column, offset), (2, 8, 13)),
+        res => panic!("expected an invalid UTF-8 error got {:?}", res),
+    }
+}
+
+#[test]
+fn test_parse_missing_final_newline() {
+    let input = "# TYPE foo counter\nfoo{a=\"b\"} 1\nfoo{a=\"c\"} 2";
+    assert!(parse_complete(input).is_err());
+
+    let res = parse_complete_with_options(input, &ParseOptions::lenient()).unwrap();
+    assert_eq!(res.len(), 1);
+    assert_metric(
+        &res[0],
+        "foo",
+        MetricType::Counter,
+        vec![
+            Sample::new(1f64, None, vec!["a", "b"]),
+            Sample::new(2f64, None, vec!["a", "c"]),
+        ],
+    );
+
+    // A single unterminated line
+    let res = parse_complete_with_options("foo 1", &ParseOptions::lenient()).unwrap();
+    assert_metric(
+        &res[0],
+        "foo",
+        MetricType::Untyped,
+        vec![Sample::new(1f64, None, vec![])],
+    );
+    // Still fails if the last line is invalid
+    assert!(matches!(
+        parse_complete_with_options("foo 1\nfoo", &ParseOptions::lenient()),
+        Result::Err(Err::Parse { line: 2, .. })
+    ));
+}
+
+#[test]
+fn test_parse_error_line() {
+    let input = "a 1\n\n# TYPE b counter\nb{ 2\r\nc 3\n";
+    match parse_complete(input) {
+        Result::Err(e @ Err::Parse { .. }) => {
+            assert_eq!(
+                e.to_string(),
+                "parse error at line 4: invalid line \"b{ 2\""
+            )
+        }
+        res => panic!("expected a parse error got {:?}", res),
+    }
+    // Read line by line or not, the error is the same
+    assert_eq!(
+        format!("{:?}", parse_reader(input.as_bytes())),
+        format!("{:?}", parse_complete(input))
+    );
+}
+
+#[test]
+fn test_parse_reader() {
+    let input = "# TYPE foo counter\r\nfoo{a=\"b\"} 1\n\nbar 2";
+    assert!(parse_reader(input.as_bytes()).is_err());
+    assert_eq!(
+        parse_reader_with_options(input.as_bytes(), &ParseOptions::lenient()).unwrap(),
+        parse_complete_with_options(input, &ParseOptions::lenient()).unwrap()
+    );
+
+    match parse_reader(&b"foo 1\nbar{a=\"\xff\"} 2\n"[..]) {
+        Result::Err(Err::InvalidUtf8 {
+            line,
+            column,
+            offset,
+        }) => assert_eq!((line, column, offset), (2, 8, 13)),
+        res => panic!("expected an invalid UTF-8 error got {:?}", res),
+    }
+}
+
+#[test]
+fn test_parse_limits() {
+    let input = "# TYPE foo counter\nfoo{a=\"b\",c=\"d\"} 1\nfoo{a=\"long value\"} 2\nbar 3\n";
+    let check = |options: ParseOptions, expected: Option<(Limit, usize)>| {
+        let results = vec![
+            parse_complete_with_options(input, &options),
+            parse_reader_with_options(input.as_bytes(), &options),
+        ];
+        for res in results {
+            match (res, expected) {
+                (Ok(_), None) => {}
+                (Result::Err(Err::LimitExceeded { limit, line }), Some(e)) => {
+                    assert_eq!((limit, line), e)
+                }
+                (res, _) => panic!("expected {:?} got {:?}", expected, res),
+            }
+        }
+    };
+    check(ParseOptions::default(), None);
+    let options = ParseOptions {
+        sample_limit: Some(3),
+        label_limit: Some(2),
+        label_name_length_limit: Some(1),
+        label_value_length_limit: Some(10),
+        body_size_limit: Some(input.len()),
+        ..ParseOptions::default()
+    };
+    check(options.clone(), None);
+    check(
+        ParseOptions {
+            sample_limit: Some(2),
+            ..options.clone()
+        },
+        Some((Limit::Samples(2), 4)),
+    );
+    check(
+        ParseOptions {
+            label_limit: Some(1),
+            ..options.clone()
+        },
+        Some((Limit::Labels(1), 2)),
+    );
+    check(
+        ParseOptions {
+            label_value_length_limit: Some(9),
+            ..options.clone()
+        },
+        Some((Limit::LabelValueLength(9), 3)),
+    );
+    check(
+        ParseOptions {
+            label_name_length_limit: Some(0),
+            ..options.clone()
+        },
+        Some((Limit::LabelNameLength(0), 2)),
+    );
+
+    let res = parse_reader_with_options(
+        input.as_bytes(),
+        &ParseOptions {
+            body_size_limit: Some(20),
+            ..ParseOptions::default()
+        },
+    );
+    assert!(
+        matches!(
+            res,
+            Result::Err(Err::LimitExceeded {
+                limit: Limit::BodySize(20),
+                line: 2
+            })
+        ),
+        "{:?}",
+        res
+    );
+
+    // An endless line is only read up to the limit
+    let res = parse_reader_with_options(
+        std::io::BufReader::new(std::io::repeat(b'a')),
+        &ParseOptions {
+            body_size_limit: Some(1000),
+            ..ParseOptions::default()
+        },
+    );
+    assert!(
+        matches!(
+            res,
+            Result::Err(Err::LimitExceeded {
+                limit: Limit::BodySize(1000),
+                line: 1
+            })
+        ),
+        "{:?}",
+        res
+    );
+}
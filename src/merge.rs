@@ -0,0 +1,280 @@
+use crate::labels::Labels;
+use crate::types::{Metric, MetricType};
+use std::collections::{BTreeMap, HashMap};
+
+/// Metrics coming from a single source like a file or a target
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub name: String,
+    pub metrics: Vec<Metric>,
+}
+
+/// Options for `merge`
+#[derive(Debug, Clone, Default)]
+pub struct MergeOptions {
+    /// Add a label with this name holding the name of the source to every sample
+    pub source_label: Option<String>,
+}
+
+/// An inconsistency between sources found while merging, the metric or sample from `source` is dropped
+#[derive(Debug, Clone, PartialEq)]
+pub enum Conflict {
+    /// The metric has another type in a source merged before
+    Type {
+        name: String,
+        source: String,
+        expected: MetricType,
+        actual: MetricType,
+    },
+    /// The metric has another docstring in a source merged before
+    Help {
+        name: String,
+        source: String,
+        expected: String,
+        actual: String,
+    },
+    /// A sample with the same name and labels was already merged
+    DuplicateSeries {
+        name: String,
+        labels: Labels,
+        source: String,
+    },
+}
+
+/// The result of `merge`, metrics are merged even if there are conflicts
+#[derive(Debug, Clone, PartialEq)]
+pub struct Merged {
+    pub metrics: Vec<Metric>,
+    pub conflicts: Vec<Conflict>,
+}
+
+struct Family {
+    metric: Metric,
+    // The series merged so far and the source they come from
+    series: HashMap<Labels, String>,
+}
+
+/// Merge metrics from multiple sources the way node_exporter's textfile collector does.
+/// The first source defining the type and docstring of a metric wins, metrics from other sources disagreeing with it are dropped
+/// and reported as conflicts, so are samples duplicating a series that was already merged.
+/// An untyped metric never conflicts with a typed one whatever their order, the merged metric has the type.
+/// A docstring is only taken from a metric that isn't entirely dropped as duplicates.
+pub fn merge_sources(sources: Vec<Source>, options: &MergeOptions) -> Merged {
+    let mut families: BTreeMap<String, Family> = BTreeMap::new();
+    let mut conflicts = Vec::new();
+    for source in sources {
+        for mut m in source.metrics {
+            if let Some(label) = &options.source_label {
+                for s in m.samples.iter_mut() {
+                    s.labels.insert(label, &source.name);
+                }
+            }
+            let family = families
+                .entry(m.name.to_string())
+                .or_insert_with(|| Family {
+                    metric: Metric::new(&m.name, m.data_type.clone()),
+                    series: HashMap::new(),
+                });
+            // A metric without a type or docstring doesn't conflict with one that has them
+            if family.metric.data_type == MetricType::Untyped {
+                family.metric.data_type = m.data_type.clone();
+            }
+            if m.data_type != family.metric.data_type && m.data_type != MetricType::Untyped {
+                conflicts.push(Conflict::Type {
+                    name: m.name.to_string(),
+                    source: source.name.clone(),
+                    expected: family.metric.data_type.clone(),
+                    actual: m.data_type,
+                });
+                continue;
+            }
+            match (&family.metric.help, &m.help) {
+                (Some(expected), Some(actual)) if expected != actual => {
+                    conflicts.push(Conflict::Help {
+                        name: m.name.to_string(),
+                        source: source.name.clone(),
+                        expected: expected.clone(),
+                        actual: actual.clone(),
+                    });
+                    continue;
+                }
+                _ => {}
+            }
+            let had_samples = !m.samples.is_empty();
+            let mut merged = false;
+            for s in m.samples {
+                if family.series.contains_key(&s.labels) {
+                    conflicts.push(Conflict::DuplicateSeries {
+                        name: m.name.to_string(),
+                        labels: s.labels,
+                        source: source.name.clone(),
+                    });
+                    continue;
+                }
+                family.series.insert(s.labels.clone(), source.name.clone());
+                family.metric.samples.push(s);
+                merged = true;
+            }
+            if family.metric.help.is_none() && (merged || !had_samples) {
+                family.metric.help = m.help;
+            }
+        }
+    }
+    Merged {
+        metrics: families.into_values().map(|f| f.metric).collect(),
+        conflicts,
+    }
+}
+
+/// Merge metrics from multiple sources, sources are named after their index in `sources`
+pub fn merge(sources: Vec<Vec<Metric>>, options: &MergeOptions) -> Merged {
+    merge_sources(
+        sources
+            .into_iter()
+            .enumerate()
+            .map(|(i, metrics)| Source {
+                name: i.to_string(),
+                metrics,
+            })
+            .collect(),
+        options,
+    )
+}
+
+#[cfg(test)]
+fn parse(s: &str) -> Vec<Metric> {
+    crate::parse_complete(s).unwrap()
+}
+
+#[test]
+fn test_merge() {
+    let res = merge(
+        vec![
+            parse("# HELP a Some help\n# TYPE a counter\na{x=\"1\"} 1\nb 2\n"),
+            parse("# TYPE a counter\na{x=\"2\"} 3\nc 4\n"),
+        ],
+        &MergeOptions::default(),
+    );
+    assert_eq!(res.conflicts, vec![]);
+    assert_eq!(
+        crate::render::render_metrics(&res.metrics),
+        "# HELP a Some help\n# TYPE a counter\na{x=\"1\"} 1\na{x=\"2\"} 3\nb 2\nc 4\n"
+    );
+}
+
+#[test]
+fn test_merge_source_label() {
+    let res = merge_sources(
+        vec![
+            Source {
+                name: "first.prom".to_string(),
+                metrics: parse("a 1\n"),
+            },
+            Source {
+                name: "second.prom".to_string(),
+                metrics: parse("a 1\n"),
+            },
+        ],
+        &MergeOptions {
+            source_label: Some("file".to_string()),
+        },
+    );
+    assert_eq!(res.conflicts, vec![]);
+    assert_eq!(
+        crate::render::render_metrics(&res.metrics),
+        "a{file=\"first.prom\"} 1\na{file=\"second.prom\"} 1\n"
+    );
+}
+
+#[test]
+fn test_merge_conflicts() {
+    let res = merge(
+        vec![
+            parse("# HELP a Some help\n# TYPE a counter\na 1\nb{x=\"y\"} 2\nc 3\n"),
+            parse("# TYPE a gauge\na 4\nb{x=\"y\"} 5\n# HELP c Other help\nc 6\n"),
+        ],
+        &MergeOptions::default(),
+    );
+    assert_eq!(
+        res.conflicts,
+        vec![
+            Conflict::Type {
+                name: "a".to_string(),
+                source: "1".to_string(),
+                expected: MetricType::Counter,
+                actual: MetricType::Gauge,
+            },
+            Conflict::DuplicateSeries {
+                name: "b".to_string(),
+                labels: Labels::from_pairs(&[("x", "y")]),
+                source: "1".to_string(),
+            },
+            Conflict::DuplicateSeries {
+                name: "c".to_string(),
+                labels: Labels::new(),
+                source: "1".to_string(),
+            },
+        ]
+    );
+    assert_eq!(
+        crate::render::render_metrics(&res.metrics),
+        "# HELP a Some help\n# TYPE a counter\na 1\nb{x=\"y\"} 2\nc 3\n"
+    );
+
+    let res = merge(
+        vec![
+            parse("# HELP a Some help\na 1\n"),
+            parse("# HELP a Other help\na 2\n"),
+        ],
+        &MergeOptions::default(),
+    );
+    assert_eq!(
+        res.conflicts,
+        vec![Conflict::Help {
+            name: "a".to_string(),
+            source: "1".to_string(),
+            expected: "Some help".to_string(),
+            actual: "Other help".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn test_merge_untyped() {
+    let untyped = "a{x=\"1\"} 1\n";
+    let counter = "# TYPE a counter\na{x=\"2\"} 2\n";
+    let expected = "# TYPE a counter\na{x=\"1\"} 1\na{x=\"2\"} 2\n";
+    let res = merge(
+        vec![parse(untyped), parse(counter)],
+        &MergeOptions::default(),
+    );
+    assert_eq!(res.conflicts, vec![]);
+    assert_eq!(crate::render::render_metrics(&res.metrics), expected);
+
+    let res = merge(
+        vec![parse(counter), parse(untyped)],
+        &MergeOptions::default(),
+    );
+    assert_eq!(res.conflicts, vec![]);
+    let mut metrics = res.metrics;
+    metrics[0].samples.reverse();
+    assert_eq!(crate::render::render_metrics(&metrics), expected);
+}
+
+#[test]
+fn test_merge_help_of_duplicates() {
+    // The docstring of a metric whose samples are all dropped isn't kept
+    let res = merge(
+        vec![parse("a 1\n"), parse("# HELP a Some help\na 2\n")],
+        &MergeOptions::default(),
+    );
+    assert_eq!(res.conflicts.len(), 1);
+    assert_eq!(res.metrics[0].help, None);
+
+    let res = merge(
+        vec![parse("a 1\n"), parse("# HELP a Some help\n")],
+        &MergeOptions::default(),
+    );
+    assert_eq!(res.conflicts, vec![]);
+    assert_eq!(res.metrics[0].help, Some("Some help".to_string()));
+}
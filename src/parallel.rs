@@ -0,0 +1,300 @@
+//! Parse large inputs on several threads with rayon.
+//! The input is split at family boundaries, the chunks are parsed in parallel and their lines are then
+//! added in the order of the input so the result is exactly the one of `parse_complete`.
+use crate::comment::CommentType;
+use crate::types::{Err, Limit, Metric, MetricType, ParseOptions, Sample};
+use crate::{add_comment, into_sorted_metrics, split_unterminated, InputIter, Limits, LineType};
+use rayon::prelude::*;
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// Chunks are at least this large so small inputs aren't split for nothing
+const MIN_CHUNK_SIZE: usize = 64 * 1024;
+
+/// The metric name of a sample, `# HELP` or `# TYPE` line and whether it's one of these comments
+fn line_name(line: &str) -> Option<(&str, bool)> {
+    let (rest, comment) = match line.strip_prefix('#') {
+        Some(c) => match c.trim_start().split_once(char::is_whitespace) {
+            Some(("HELP", rest)) | Some(("TYPE", rest)) => (rest.trim_start(), true),
+            _ => return None,
+        },
+        None => match line.trim_start() {
+            // A quoted name written inside the braces
+            l if l.starts_with('{') => (l[1..].trim_start(), false),
+            l => (l, false),
+        },
+    };
+    if let Some(quoted) = rest.strip_prefix('"') {
+        // The raw name is enough to compare lines, escape sequences are skipped over
+        let mut escaped = false;
+        let end = quoted.find(|c| {
+            let end = !escaped && c == '"';
+            escaped = !escaped && c == '\\';
+            end
+        })?;
+        return Some((&quoted[..end], comment)).filter(|(n, _)| !n.is_empty());
+    }
+    let end = rest
+        .find(|c: char| c == '{' || c.is_ascii_whitespace())
+        .unwrap_or(rest.len());
+    Some((&rest[..end], comment)).filter(|(n, _)| !n.is_empty())
+}
+
+/// The start of the first line after `from` beginning a family: a line about another metric than the previous one,
+/// or a `# HELP` or `# TYPE` line following a sample. `from` must be the start of a line.
+fn next_boundary(input: &str, from: usize) -> Option<usize> {
+    let mut previous: Option<(&str, bool)> = None;
+    let mut start = from;
+    while start < input.len() {
+        let end = input[start..]
+            .find('\n')
+            .map_or(input.len(), |i| start + i + 1);
+        if let Some((name, comment)) = line_name(&input[start..end]) {
+            if let Some((p, p_comment)) = previous {
+                if p != name || (comment && !p_comment) {
+                    return Some(start);
+                }
+            }
+            previous = Some((name, comment));
+        }
+        start = end;
+    }
+    None
+}
+
+/// Split the input in chunks of about `size` bytes ending at family boundaries
+fn split_families(input: &str, size: usize) -> Vec<&str> {
+    let mut res = Vec::new();
+    let mut rest = input;
+    while rest.len() > size {
+        // Start looking for a boundary after the line containing the byte at `size`
+        let from = rest.as_bytes()[..size]
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |i| i + 1);
+        match next_boundary(rest, from) {
+            Some(i) => {
+                res.push(&rest[..i]);
+                rest = &rest[i..];
+            }
+            None => break,
+        }
+    }
+    if !rest.is_empty() {
+        res.push(rest);
+    }
+    res
+}
+
+/// A line parsed in a chunk, samples are already converted so allocating them happens in parallel
+enum Parsed<'a> {
+    Comment(CommentType<'a>),
+    Sample {
+        name: Cow<'a, str>,
+        sample: Sample,
+        line: usize,
+    },
+}
+
+/// The lines of a chunk until the first error
+struct Chunk<'a> {
+    lines: Vec<Parsed<'a>>,
+    err: Option<Err>,
+}
+
+fn parse_chunk<'a>(input: &'a str, first_line: usize, options: &ParseOptions) -> Chunk<'a> {
+    // The sample limit is checked when merging as it depends on the previous chunks
+    let options = ParseOptions {
+        sample_limit: None,
+        ..options.clone()
+    };
+    let mut limits = Limits {
+        line: first_line,
+        samples: 0,
+    };
+    let mut lines = Vec::new();
+    for l in InputIter(input) {
+        let l = match l
+            .map_err(|rest| Err::parse(limits.line + 1, rest))
+            .and_then(|l| limits.check_line(&l, &options).map(|_| l))
+        {
+            Ok(l) => l,
+            Result::Err(e) => {
+                return Chunk {
+                    lines,
+                    err: Some(e),
+                }
+            }
+        };
+        match l {
+            LineType::Comment(c) => lines.push(Parsed::Comment(c)),
+            LineType::Sample(mut s) => lines.push(Parsed::Sample {
+                name: std::mem::take(&mut s.name),
+                sample: s.into(),
+                line: limits.line,
+            }),
+            LineType::Empty => {}
+        }
+    }
+    Chunk { lines, err: None }
+}
+
+fn parse_chunks(chunks: &[&str], options: &ParseOptions) -> Result<Vec<Metric>, Err> {
+    let line_counts: Vec<usize> = chunks
+        .par_iter()
+        .map(|c| c.bytes().filter(|&b| b == b'\n').count())
+        .collect();
+    let first_lines: Vec<usize> = line_counts
+        .iter()
+        .scan(0, |acc, n| {
+            let first = *acc;
+            *acc += n;
+            Some(first)
+        })
+        .collect();
+    let parsed: Vec<Chunk> = chunks
+        .par_iter()
+        .zip(first_lines.par_iter())
+        .map(|(c, &first)| parse_chunk(c, first, options))
+        .collect();
+
+    let mut map = HashMap::new();
+    let mut samples = 0;
+    for chunk in parsed {
+        for l in chunk.lines {
+            match l {
+                Parsed::Comment(c) => add_comment(&mut map, c, None),
+                Parsed::Sample { name, sample, line } => {
+                    samples += 1;
+                    if let Some(l) = options.sample_limit.filter(|&l| samples > l) {
+                        return Err(Err::LimitExceeded {
+                            limit: Limit::Samples(l),
+                            line,
+                        });
+                    }
+                    match map.get_mut(&*name) {
+                        Some(m) => m.push_sample(sample),
+                        None => {
+                            let mut m = Metric::new(&name, MetricType::Untyped);
+                            m.push_sample(sample);
+                            map.insert(m.name.clone(), m);
+                        }
+                    }
+                }
+            }
+        }
+        if let Some(e) = chunk.err {
+            return Err(e);
+        }
+    }
+    Ok(into_sorted_metrics(map))
+}
+
+fn parse_with_chunk_size(
+    input: &str,
+    options: &ParseOptions,
+    size: usize,
+) -> Result<Vec<Metric>, Err> {
+    Limits::default().check_body_size(input.len(), options)?;
+    let (input, last_line) = split_unterminated(input, options);
+    let last_line = last_line.map(|l| format!("{}\n", l));
+    let mut chunks = split_families(input, size);
+    chunks.extend(last_line.as_deref());
+    parse_chunks(&chunks, options)
+}
+
+/// Parse a string on the rayon thread pool, the result is the same as `parse_complete`
+pub fn parse_complete_parallel(input: &str) -> Result<Vec<Metric>, Err> {
+    parse_complete_parallel_with_options(input, &ParseOptions::default())
+}
+
+/// Parse a string with specific options on the rayon thread pool, the result is the same as `parse_complete_with_options`
+pub fn parse_complete_parallel_with_options(
+    input: &str,
+    options: &ParseOptions,
+) -> Result<Vec<Metric>, Err> {
+    let size = input.len() / (rayon::current_num_threads() * 4);
+    parse_with_chunk_size(input, options, size.max(MIN_CHUNK_SIZE))
+}
+
+#[test]
+fn test_split_families() {
+    let input = "# TYPE a counter\na 1\na{x=\"y\"} 2\nb 3\n# HELP c doc\n# TYPE c gauge\nc 4\n";
+    assert_eq!(
+        split_families(input, 1),
+        vec![
+            "# TYPE a counter\na 1\na{x=\"y\"} 2\n",
+            "b 3\n",
+            "# HELP c doc\n# TYPE c gauge\nc 4\n"
+        ]
+    );
+    assert_eq!(split_families(input, 1000), vec![input]);
+    assert_eq!(split_families("", 1), Vec::<&str>::new());
+
+    let input = "# TYPE \"a.b\" counter\n{\"a.b\"} 1\n{ \"a.b\",x=\"y\"} 2\n{\"a\\\"c\"} 3\n";
+    assert_eq!(
+        split_families(input, 1),
+        vec![
+            "# TYPE \"a.b\" counter\n{\"a.b\"} 1\n{ \"a.b\",x=\"y\"} 2\n",
+            "{\"a\\\"c\"} 3\n"
+        ]
+    );
+}
+
+#[test]
+fn test_parse_parallel() {
+    let input = r#"# TYPE foo summary
+foo{quantile="0.5"} 1
+foo_sum 2
+
+# TYPE foo summary
+foo{quantile="0.9"} 3
+bar 4
+foo_count 5
+# HELP bar some doc
+bar{a="b"} 6
+"#;
+    for size in 1..input.len() {
+        assert_eq!(
+            parse_with_chunk_size(input, &ParseOptions::default(), size).unwrap(),
+            crate::parse_complete(input).unwrap(),
+            "chunks of {} bytes",
+            size
+        );
+    }
+    let options = ParseOptions {
+        sample_limit: Some(4),
+        ..ParseOptions::lenient()
+    };
+    let expected = format!("{:?}", crate::parse_complete_with_options(input, &options));
+    for size in 1..input.len() {
+        assert_eq!(
+            format!("{:?}", parse_with_chunk_size(input, &options, size)),
+            expected
+        );
+    }
+    // Errors are reported at their line in the whole input, whatever the chunk they're in
+    let invalid = format!("{}baz{{ 1\nqux 2\n", input);
+    let expected = format!("{:?}", crate::parse_complete(&invalid));
+    assert!(
+        expected.starts_with("Err(Parse { line: 11,"),
+        "{}",
+        expected
+    );
+    for size in 1..invalid.len() {
+        assert_eq!(
+            format!(
+                "{:?}",
+                parse_with_chunk_size(&invalid, &ParseOptions::default(), size)
+            ),
+            expected,
+            "chunks of {} bytes",
+            size
+        );
+    }
+    assert_eq!(format!("{:?}", parse_complete_parallel(&invalid)), expected);
+    assert_eq!(
+        parse_complete_parallel_with_options("a 1\nb 2", &ParseOptions::lenient()).unwrap(),
+        crate::parse_complete_with_options("a 1\nb 2", &ParseOptions::lenient()).unwrap()
+    );
+}
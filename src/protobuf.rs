@@ -0,0 +1,245 @@
+//! Encoding of metrics in the Prometheus protobuf format.
+//! See [metrics.proto](https://github.com/prometheus/client_model/blob/master/io/prometheus/client/metrics.proto),
+//! the messages are simple enough to be encoded by hand.
+use crate::labels::Labels;
+use crate::render::families;
+use crate::types::{Metric, MetricType};
+use std::collections::HashMap;
+
+/// The content type of a body made of length delimited `MetricFamily` messages
+pub const CONTENT_TYPE: &str =
+    "application/vnd.google.protobuf; proto=io.prometheus.client.MetricFamily; encoding=delimited";
+
+#[derive(Default)]
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn varint(&mut self, mut v: u64) {
+        while v >= 0x80 {
+            self.0.push((v as u8) | 0x80);
+            v >>= 7;
+        }
+        self.0.push(v as u8);
+    }
+
+    fn key(&mut self, field: u64, wire_type: u64) {
+        self.varint(field << 3 | wire_type);
+    }
+
+    fn uint64(&mut self, field: u64, v: u64) {
+        self.key(field, 0);
+        self.varint(v);
+    }
+
+    fn int64(&mut self, field: u64, v: i64) {
+        self.key(field, 0);
+        self.varint(v as u64);
+    }
+
+    fn double(&mut self, field: u64, v: f64) {
+        self.key(field, 1);
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn bytes(&mut self, field: u64, v: &[u8]) {
+        self.key(field, 2);
+        self.varint(v.len() as u64);
+        self.0.extend_from_slice(v);
+    }
+
+    fn message<F: FnOnce(&mut Writer)>(&mut self, field: u64, f: F) {
+        let mut w = Writer::default();
+        f(&mut w);
+        self.bytes(field, &w.0);
+    }
+}
+
+#[derive(Default)]
+struct Series {
+    // (upper bound or quantile, value)
+    points: Vec<(f64, f64)>,
+    sum: f64,
+    count: f64,
+    timestamp: Option<i64>,
+}
+
+/// Rebuild the histograms or summaries from their `_bucket`, `_sum` and `_count` samples
+fn group_series<'a>(
+    metric: &'a Metric,
+    children: &[&'a Metric],
+    excluded: &str,
+) -> Vec<(Labels, Series)> {
+    let mut order = Vec::new();
+    let mut series: HashMap<Labels, Series> = HashMap::new();
+    let all = std::iter::once(metric).chain(children.iter().copied());
+    for m in all {
+        let suffix = &m.name[metric.name.len()..];
+        for s in &m.samples {
+            // `le` and `quantile` aren't part of the series identity
+            let key = s.labels.without(&[excluded]);
+            if !series.contains_key(&key) {
+                order.push(key.clone());
+            }
+            let entry = series.entry(key).or_default();
+            entry.timestamp = entry.timestamp.or(s.timestamp);
+            match suffix {
+                "_sum" => entry.sum = s.value,
+                "_count" => entry.count = s.value,
+                _ => {
+                    let bound = s
+                        .labels
+                        .get(excluded)
+                        .and_then(crate::samples::parse_float)
+                        .unwrap_or(f64::NAN);
+                    entry.points.push((bound, s.value))
+                }
+            }
+        }
+    }
+    order
+        .into_iter()
+        .map(|k| {
+            let s = series.remove(&k).unwrap_or_default();
+            (k, s)
+        })
+        .collect()
+}
+
+fn write_labels(w: &mut Writer, labels: &Labels) {
+    for (k, v) in labels {
+        w.message(1, |w| {
+            w.bytes(1, k.as_bytes());
+            w.bytes(2, v.as_bytes());
+        });
+    }
+}
+
+fn write_family(w: &mut Writer, metric: &Metric, children: &[&Metric]) {
+    let type_value = match metric.data_type {
+        MetricType::Counter => 0,
+        MetricType::Gauge => 1,
+        MetricType::Summary => 2,
+        MetricType::Untyped => 3,
+        MetricType::Histogram => 4,
+    };
+    w.bytes(1, metric.name.as_bytes());
+    if let Some(doc) = &metric.help {
+        w.bytes(2, doc.as_bytes());
+    }
+    w.uint64(3, type_value);
+    match metric.data_type {
+        MetricType::Histogram | MetricType::Summary => {
+            let histogram = metric.data_type == MetricType::Histogram;
+            let excluded = if histogram { "le" } else { "quantile" };
+            for (labels, s) in group_series(metric, children, excluded) {
+                w.message(4, |w| {
+                    write_labels(w, &labels);
+                    if histogram {
+                        w.message(7, |w| {
+                            w.uint64(1, s.count as u64);
+                            w.double(2, s.sum);
+                            for (bound, v) in &s.points {
+                                w.message(3, |w| {
+                                    w.uint64(1, *v as u64);
+                                    w.double(2, *bound);
+                                });
+                            }
+                        });
+                    } else {
+                        w.message(4, |w| {
+                            w.uint64(1, s.count as u64);
+                            w.double(2, s.sum);
+                            for (quantile, v) in &s.points {
+                                w.message(3, |w| {
+                                    w.double(1, *quantile);
+                                    w.double(2, *v);
+                                });
+                            }
+                        });
+                    }
+                    if let Some(ts) = s.timestamp {
+                        w.int64(6, ts);
+                    }
+                });
+            }
+        }
+        ref t => {
+            let field = match t {
+                MetricType::Gauge => 2,
+                MetricType::Counter => 3,
+                _ => 5,
+            };
+            for s in &metric.samples {
+                w.message(4, |w| {
+                    write_labels(w, &s.labels);
+                    w.message(field, |w| w.double(1, s.value));
+                    if let Some(ts) = s.timestamp {
+                        w.int64(6, ts);
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// Encode metrics as length delimited `MetricFamily` messages.
+/// Histograms and summaries are rebuilt from the metrics holding their `_bucket`, `_sum` and `_count` samples.
+pub fn encode(metrics: &[Metric]) -> Vec<u8> {
+    let mut res = Writer::default();
+    for f in families(metrics) {
+        let mut family = Writer::default();
+        write_family(&mut family, f.metric, &f.children);
+        res.varint(family.0.len() as u64);
+        res.0.extend_from_slice(&family.0);
+    }
+    res.0
+}
+
+#[test]
+fn test_varint() {
+    let mut w = Writer::default();
+    w.varint(1);
+    w.varint(300);
+    assert_eq!(w.0, vec![1, 0xac, 0x02]);
+}
+
+#[test]
+fn test_encode_counter() {
+    let metrics = crate::parse_complete("# TYPE foo counter\nfoo{a=\"b\"} 1 2\n").unwrap();
+    let mut counter = vec![0x1a, 9, 0x09];
+    counter.extend_from_slice(&1f64.to_le_bytes());
+    let mut metric = vec![0x22, 21];
+    // The label pair
+    metric.extend_from_slice(&[0x0a, 6, 0x0a, 1, b'a', 0x12, 1, b'b']);
+    metric.extend_from_slice(&counter);
+    // The timestamp
+    metric.extend_from_slice(&[0x30, 2]);
+    // The family length, name and type
+    let mut expected = vec![30, 0x0a, 3, b'f', b'o', b'o', 0x18, 0];
+    expected.extend_from_slice(&metric);
+    assert_eq!(encode(&metrics), expected);
+}
+
+#[test]
+fn test_encode_histogram() {
+    let metrics = crate::parse_complete(
+        r#"# TYPE foo histogram
+foo_bucket{le="1"} 2
+foo_bucket{le="+Inf"} 3
+foo_sum 4
+foo_count 3
+"#,
+    )
+    .unwrap();
+    let encoded = encode(&metrics);
+    // A single family
+    assert_eq!(encoded[0] as usize, encoded.len() - 1);
+    let mut bucket = vec![0x1a, 11, 0x08, 2, 0x11];
+    bucket.extend_from_slice(&1f64.to_le_bytes());
+    assert!(encoded.windows(bucket.len()).any(|w| w == &bucket[..]));
+    let mut inf_bucket = vec![0x1a, 11, 0x08, 3, 0x11];
+    inf_bucket.extend_from_slice(&f64::INFINITY.to_le_bytes());
+    assert!(encoded
+        .windows(inf_bucket.len())
+        .any(|w| w == &inf_bucket[..]));
+}
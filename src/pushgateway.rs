@@ -0,0 +1,395 @@
+//! A lightweight replacement for the [Pushgateway](https://github.com/prometheus/pushgateway).
+//! Batch jobs push their metrics to `/metrics/job/<job>{/<label>/<value>}` and everything pushed is exposed on `/metrics`.
+use crate::compression::{parse_encoded, Encoding};
+use crate::http::{self, Request, Response};
+use crate::server::metrics_response;
+use crate::types::{Metric, MetricType, ParseOptions, Sample};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use std::collections::BTreeMap;
+use std::io;
+use std::net::TcpListener;
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The labels identifying a group of pushed metrics, `job` is always first
+pub type GroupingKey = Vec<(String, String)>;
+
+struct Group {
+    metrics: BTreeMap<String, Metric>,
+    push_time_seconds: f64,
+}
+
+/// The metrics pushed so far, grouped by grouping key
+#[derive(Default)]
+pub struct Pushgateway {
+    groups: RwLock<BTreeMap<GroupingKey, Group>>,
+}
+
+/// Decode the `%XX` escapes of a path segment like client libraries write them
+fn percent_decode(segment: &str) -> Result<String, String> {
+    let invalid = || format!("invalid escape in path segment {}", segment);
+    let mut bytes = Vec::with_capacity(segment.len());
+    let mut rest = segment.as_bytes();
+    while let Some((&b, r)) = rest.split_first() {
+        if b == b'%' {
+            let hex = r
+                .get(..2)
+                .filter(|h| h.iter().all(u8::is_ascii_hexdigit))
+                .and_then(|h| std::str::from_utf8(h).ok())
+                .ok_or_else(invalid)?;
+            bytes.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+            rest = &r[2..];
+        } else {
+            bytes.push(b);
+            rest = r;
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| format!("invalid UTF-8 in path segment {}", segment))
+}
+
+fn decode_segment(name: &str, value: &str) -> Result<(String, String), String> {
+    let (name, value) = (percent_decode(name)?, percent_decode(value)?);
+    match name.strip_suffix("@base64") {
+        Some(name) => {
+            // `=` alone is the encoding of the empty string
+            let bytes = URL_SAFE_NO_PAD
+                .decode(value.trim_end_matches('='))
+                .map_err(|e| format!("invalid base64 value for label {}: {}", name, e))?;
+            let value = String::from_utf8(bytes)
+                .map_err(|_| format!("invalid UTF-8 value for label {}", name))?;
+            Ok((name.to_string(), value))
+        }
+        None => Ok((name, value)),
+    }
+}
+
+/// Read the grouping key from a path like `/metrics/job/<job>{/<label>/<value>}`.
+/// Segments are percent-decoded, then label names with the `@base64` suffix have a value encoded in URL safe base64.
+/// Labels with an empty value are left out of the key.
+pub fn parse_grouping_key(path: &str) -> Result<GroupingKey, String> {
+    let rest = path
+        .strip_prefix("/metrics/")
+        .ok_or_else(|| format!("invalid path {}", path))?;
+    let segments: Vec<&str> = rest.trim_end_matches('/').split('/').collect();
+    if !segments.len().is_multiple_of(2) {
+        return Err(format!("odd number of segments in {}", path));
+    }
+    let mut key: GroupingKey = Vec::new();
+    for pair in segments.chunks(2) {
+        let (name, value) = decode_segment(pair[0], pair[1])?;
+        if name.is_empty() || key.iter().any(|(k, _)| *k == name) {
+            return Err(format!("invalid or duplicate label name {:?}", name));
+        }
+        key.push((name, value));
+    }
+    match key.first() {
+        Some((name, value)) if name.as_str() == "job" && !value.is_empty() => {}
+        _ => return Err("the grouping key must start with a non empty job".to_string()),
+    }
+    // An empty value is the same as no label, like in Prometheus
+    key.retain(|(_, value)| !value.is_empty());
+    // Keep job first and sort the other labels so the same group always gets the same key
+    key[1..].sort();
+    Ok(key)
+}
+
+/// Add the grouping key labels to every sample, a sample already having one of these labels with another value is an error
+fn add_grouping_labels(metrics: &mut [Metric], key: &[(String, String)]) -> Result<(), String> {
+    for m in metrics.iter_mut() {
+        for s in m.samples.iter_mut() {
+            if s.timestamp.is_some() {
+                return Err(format!("pushed metric {} has a timestamp", m.name));
+            }
+            for (k, v) in key {
+                match s.labels.get(k) {
+                    Some(existing) if existing != v.as_str() => {
+                        return Err(format!(
+                            "pushed metric {} has label {}={:?} conflicting with the grouping key",
+                            m.name, k, existing
+                        ));
+                    }
+                    _ => {
+                        s.labels.insert(k, v);
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn now_seconds() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0f64, |d| d.as_secs_f64())
+}
+
+impl Pushgateway {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Store pushed metrics in a group.
+    /// With `replace` all the metrics of the group are replaced (`PUT`), otherwise only the ones with the same name are (`POST`).
+    pub fn push(
+        &self,
+        key: GroupingKey,
+        mut metrics: Vec<Metric>,
+        replace: bool,
+    ) -> Result<(), String> {
+        add_grouping_labels(&mut metrics, &key)?;
+        let mut groups = self.groups.write().unwrap_or_else(|e| e.into_inner());
+        // Types must be consistent with what other groups pushed
+        for m in &metrics {
+            let conflict = groups
+                .iter()
+                .filter(|(k, _)| **k != key)
+                .filter_map(|(_, g)| g.metrics.get(&*m.name))
+                .find(|other| other.data_type != m.data_type);
+            if let Some(other) = conflict {
+                return Err(format!(
+                    "metric {} pushed as {} but already exists as {}",
+                    m.name,
+                    m.data_type.as_str(),
+                    other.data_type.as_str()
+                ));
+            }
+        }
+        let group = groups.entry(key).or_insert_with(|| Group {
+            metrics: BTreeMap::new(),
+            push_time_seconds: 0f64,
+        });
+        if replace {
+            group.metrics.clear();
+        }
+        for m in metrics {
+            group.metrics.insert(m.name.to_string(), m);
+        }
+        group.push_time_seconds = now_seconds();
+        Ok(())
+    }
+
+    /// Delete all the metrics of a group
+    pub fn delete(&self, key: &[(String, String)]) {
+        let mut groups = self.groups.write().unwrap_or_else(|e| e.into_inner());
+        groups.remove(key);
+    }
+
+    /// All the metrics pushed so far and a `push_time_seconds` gauge per group
+    pub fn metrics(&self) -> Vec<Metric> {
+        let groups = self.groups.read().unwrap_or_else(|e| e.into_inner());
+        let mut res: BTreeMap<String, Metric> = BTreeMap::new();
+        let mut push_time = Metric::new("push_time_seconds", MetricType::Gauge);
+        for (key, group) in groups.iter() {
+            for m in group.metrics.values() {
+                match res.get_mut(&*m.name) {
+                    Some(existing) => existing.samples.extend(m.samples.iter().cloned()),
+                    None => {
+                        res.insert(m.name.to_string(), m.clone());
+                    }
+                }
+            }
+            let labels: Vec<&str> = key
+                .iter()
+                .flat_map(|(k, v)| vec![k.as_str(), v.as_str()])
+                .collect();
+            push_time.push_sample(Sample::new(group.push_time_seconds, None, labels));
+        }
+        if !push_time.samples.is_empty() {
+            res.insert(push_time.name.to_string(), push_time);
+        }
+        res.into_values().collect()
+    }
+
+    /// Answer a request to the pushgateway API
+    pub fn handle(&self, request: &Request) -> Response {
+        if request.path == "/metrics" {
+            return match request.method.as_str() {
+                "GET" => metrics_response(request, &self.metrics()),
+                _ => Response::text(405, "Method not allowed"),
+            };
+        }
+        let key = match parse_grouping_key(&request.path) {
+            Ok(key) => key,
+            Err(_) if !request.path.starts_with("/metrics/") => {
+                return Response::text(404, "Not found")
+            }
+            Err(e) => return Response::text(400, &e),
+        };
+        match request.method.as_str() {
+            "PUT" | "POST" => {
+                let encoding = match Encoding::from_content_encoding(
+                    request.header("Content-Encoding").unwrap_or(""),
+                ) {
+                    Some(e) => e,
+                    None => return Response::text(400, "unsupported content encoding"),
+                };
+                let metrics =
+                    match parse_encoded(&request.body[..], encoding, &ParseOptions::default()) {
+                        Ok(m) => m,
+                        Err(e) => return Response::text(400, &e.to_string()),
+                    };
+                match self.push(key, metrics, request.method == "PUT") {
+                    Ok(()) => Response::text(200, ""),
+                    Err(e) => Response::text(400, &e),
+                }
+            }
+            "DELETE" => {
+                self.delete(&key);
+                Response::text(202, "")
+            }
+            _ => Response::text(405, "Method not allowed"),
+        }
+    }
+}
+
+/// Serve the pushgateway API forever
+pub fn serve(listener: TcpListener, gateway: Arc<Pushgateway>) -> io::Result<()> {
+    http::serve(listener, move |r| gateway.handle(r))
+}
+
+#[cfg(test)]
+fn push(gateway: &Pushgateway, method: &str, path: &str, body: &str) -> u16 {
+    let mut request = Request::new(method, path);
+    request.body = body.as_bytes().to_vec();
+    gateway.handle(&request).status
+}
+
+#[cfg(test)]
+fn exposed(gateway: &Pushgateway) -> String {
+    // Push times change on every run
+    let metrics: Vec<Metric> = gateway
+        .metrics()
+        .into_iter()
+        .filter(|m| &*m.name != "push_time_seconds")
+        .collect();
+    crate::render::render_metrics(&metrics)
+}
+
+#[test]
+fn test_parse_grouping_key() {
+    let key = |v: Vec<(&str, &str)>| -> GroupingKey {
+        v.into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    };
+    assert_eq!(
+        parse_grouping_key("/metrics/job/backup"),
+        Ok(key(vec![("job", "backup")]))
+    );
+    assert_eq!(
+        parse_grouping_key("/metrics/job/backup/zone/b/instance/a/"),
+        Ok(key(vec![
+            ("job", "backup"),
+            ("instance", "a"),
+            ("zone", "b")
+        ]))
+    );
+    // `/var/tmp` and an empty value, which isn't added as a label
+    assert_eq!(
+        parse_grouping_key("/metrics/job/backup/path@base64/L3Zhci90bXA/empty@base64/="),
+        Ok(key(vec![("job", "backup"), ("path", "/var/tmp")]))
+    );
+    assert_eq!(
+        parse_grouping_key("/metrics/job/x/instance@base64/="),
+        Ok(key(vec![("job", "x")]))
+    );
+    assert_eq!(
+        parse_grouping_key("/metrics/job/x/instance@base64/=/zone/a"),
+        parse_grouping_key("/metrics/job/x/zone/a")
+    );
+    // Path escaped values like client_golang writes them
+    assert_eq!(
+        parse_grouping_key("/metrics/job/a%2Fb/instance/host%3A9100%20%C3%A9"),
+        Ok(key(vec![("job", "a/b"), ("instance", "host:9100 é")]))
+    );
+    assert!(parse_grouping_key("/metrics/job/a%2").is_err());
+    assert!(parse_grouping_key("/metrics/job/a%zz").is_err());
+    assert!(parse_grouping_key("/metrics/job/a%+1").is_err());
+    assert!(parse_grouping_key("/metrics/job/a%FF").is_err());
+    assert!(parse_grouping_key("/metrics/job").is_err());
+    assert!(parse_grouping_key("/metrics/instance/a").is_err());
+    assert!(parse_grouping_key("/metrics/job/a/x/1/x/2").is_err());
+    assert!(parse_grouping_key("/metrics/job@base64/!!").is_err());
+}
+
+#[test]
+fn test_put_post_delete() {
+    let gateway = Pushgateway::new();
+    assert_eq!(
+        push(
+            &gateway,
+            "PUT",
+            "/metrics/job/backup",
+            "# TYPE a gauge\na 1\nb 2\n"
+        ),
+        200
+    );
+    assert_eq!(
+        exposed(&gateway),
+        "# TYPE a gauge\na{job=\"backup\"} 1\nb{job=\"backup\"} 2\n"
+    );
+
+    // POST only replaces metrics with the same name
+    assert_eq!(
+        push(&gateway, "POST", "/metrics/job/backup", "b 3\nc 4\n"),
+        200
+    );
+    assert_eq!(
+        exposed(&gateway),
+        "# TYPE a gauge\na{job=\"backup\"} 1\nb{job=\"backup\"} 3\nc{job=\"backup\"} 4\n"
+    );
+
+    // PUT replaces the whole group
+    assert_eq!(push(&gateway, "PUT", "/metrics/job/backup", "c 5\n"), 200);
+    assert_eq!(exposed(&gateway), "c{job=\"backup\"} 5\n");
+
+    // Groups are independent
+    assert_eq!(
+        push(&gateway, "PUT", "/metrics/job/cleanup/instance/x", "c 6\n"),
+        200
+    );
+    assert_eq!(
+        exposed(&gateway),
+        "c{job=\"backup\"} 5\nc{instance=\"x\",job=\"cleanup\"} 6\n"
+    );
+    let push_time = gateway
+        .metrics()
+        .into_iter()
+        .find(|m| &*m.name == "push_time_seconds")
+        .unwrap();
+    assert_eq!(push_time.data_type, MetricType::Gauge);
+    assert_eq!(push_time.samples.len(), 2);
+    assert!(push_time.samples.iter().all(|s| s.value > 0f64));
+
+    assert_eq!(push(&gateway, "DELETE", "/metrics/job/backup", ""), 202);
+    assert_eq!(exposed(&gateway), "c{instance=\"x\",job=\"cleanup\"} 6\n");
+}
+
+#[test]
+fn test_invalid_pushes() {
+    let gateway = Pushgateway::new();
+    assert_eq!(
+        push(&gateway, "PUT", "/metrics/job/a", "# TYPE a gauge\na 1\n"),
+        200
+    );
+    // Conflicting type with another group
+    assert_eq!(
+        push(&gateway, "PUT", "/metrics/job/b", "# TYPE a counter\na 1\n"),
+        400
+    );
+    // Conflicting grouping label
+    assert_eq!(
+        push(&gateway, "PUT", "/metrics/job/b", "a{job=\"c\"} 1\n"),
+        400
+    );
+    // Timestamps aren't allowed
+    assert_eq!(push(&gateway, "PUT", "/metrics/job/b", "a 1 1234\n"), 400);
+    // Invalid body
+    assert_eq!(push(&gateway, "PUT", "/metrics/job/b", "a\n"), 400);
+    assert_eq!(push(&gateway, "PUT", "/metrics/foo", "a 1\n"), 400);
+    assert_eq!(push(&gateway, "GET", "/metrics/job/a", ""), 405);
+    assert_eq!(push(&gateway, "GET", "/other", ""), 404);
+    assert_eq!(exposed(&gateway), "# TYPE a gauge\na{job=\"a\"} 1\n");
+}
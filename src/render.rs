@@ -0,0 +1,646 @@
+use crate::common::is_legacy_name;
+use crate::labels::Labels;
+use crate::types::{Line, Metric, MetricType, Sample};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Write};
+
+/// Format a value so that it's parsed back to the exact same float by this crate and by Prometheus.
+/// Very large and very small values use the exponent notation.
+pub fn format_value(v: f64) -> String {
+    if v.is_nan() {
+        "NaN".to_string()
+    } else if v.is_infinite() {
+        if v > 0f64 { "+Inf" } else { "-Inf" }.to_string()
+    } else if v == 0f64 || (1e-4..1e15).contains(&v.abs()) {
+        format!("{}", v)
+    } else {
+        format!("{:e}", v)
+    }
+}
+
+/// Escape a label value, `\`, `"` and line breaks are the only characters that need escaping
+pub fn escape_label_value(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => res.push_str("\\\\"),
+            '"' => res.push_str("\\\""),
+            '\n' => res.push_str("\\n"),
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+/// Escape a HELP docstring, `\` and line breaks are the only characters that need escaping
+pub fn escape_help(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+/// How names that aren't valid before Prometheus 3.0 are rendered, named like the `escaping` parameter
+/// of the `Accept` header Prometheus sends when scraping
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EscapingScheme {
+    /// Names are kept and written as quoted strings
+    AllowUtf8,
+    /// Invalid characters are replaced with `_`
+    Underscores,
+    /// `.` is replaced with `_dot_`, `_` with `__` and other invalid characters with `__`
+    Dots,
+    /// Names are prefixed with `U__`, `_` is replaced with `__` and invalid characters with their code point
+    /// as `_<hex>_`, so the original name can be recovered
+    Values,
+}
+
+/// Escape a metric or label name with the same rules as Prometheus
+pub fn escape_name(name: &str, scheme: EscapingScheme) -> Cow<'_, str> {
+    let is_valid = |i: usize, c: char| {
+        c.is_ascii_alphabetic() || c == '_' || c == ':' || (i > 0 && c.is_ascii_digit())
+    };
+    let mut res = String::with_capacity(name.len());
+    match scheme {
+        EscapingScheme::AllowUtf8 => return Cow::Borrowed(name),
+        _ if name.is_empty() => return Cow::Borrowed(name),
+        EscapingScheme::Underscores | EscapingScheme::Values if is_legacy_name(name) => {
+            return Cow::Borrowed(name)
+        }
+        EscapingScheme::Underscores => {
+            for (i, c) in name.chars().enumerate() {
+                res.push(if is_valid(i, c) { c } else { '_' });
+            }
+        }
+        // Legacy names are escaped too, as `_` is
+        EscapingScheme::Dots => {
+            for (i, c) in name.chars().enumerate() {
+                match c {
+                    '_' => res.push_str("__"),
+                    '.' => res.push_str("_dot_"),
+                    c if is_valid(i, c) => res.push(c),
+                    _ => res.push_str("__"),
+                }
+            }
+        }
+        EscapingScheme::Values => {
+            res.push_str("U__");
+            for (i, c) in name.chars().enumerate() {
+                match c {
+                    '_' => res.push_str("__"),
+                    c if is_valid(i, c) => res.push(c),
+                    c => write!(res, "_{:x}_", c as u32).expect("Writing to a string never fails"),
+                }
+            }
+        }
+    }
+    Cow::Owned(res)
+}
+
+/// Escape the name of a sample made of its family name and a suffix like `_bucket` or `_total`.
+/// The family name is escaped alone so the sample name still starts with it.
+/// The suffix is kept except with `Values` which doubles its `_` to tell it apart from escaped characters.
+fn escape_suffixed_name(family: &str, suffix: &str, scheme: EscapingScheme) -> String {
+    match scheme {
+        EscapingScheme::Values if !is_legacy_name(family) => format!(
+            "{}{}",
+            escape_name(family, scheme),
+            suffix.replace('_', "__")
+        ),
+        _ => format!("{}{}", escape_name(family, scheme), suffix),
+    }
+}
+
+/// Escape the names of metrics and the names of their labels.
+/// The `_bucket`, `_sum` and `_count` metrics of histograms and summaries and the `_total` suffix of counters
+/// are added to their escaped family name, so they still belong to it.
+/// Different names may end up the same, the metrics aren't merged.
+pub fn escape_names(metrics: &mut [Metric], scheme: EscapingScheme) {
+    if scheme == EscapingScheme::AllowUtf8 {
+        return;
+    }
+    let mut children: HashMap<String, String> = families(metrics)
+        .iter()
+        .flat_map(|f| {
+            let family = &f.metric.name;
+            f.children.iter().map(move |c| {
+                let suffix = &c.name[family.len()..];
+                (
+                    c.name.to_string(),
+                    escape_suffixed_name(family, suffix, scheme),
+                )
+            })
+        })
+        .collect();
+    for m in metrics {
+        let name = match children.remove(&*m.name) {
+            Some(name) => Cow::Owned(name),
+            None => match m.name.strip_suffix("_total") {
+                Some(family) if m.data_type == MetricType::Counter && !family.is_empty() => {
+                    Cow::Owned(escape_suffixed_name(family, "_total", scheme))
+                }
+                _ => escape_name(&m.name, scheme),
+            },
+        };
+        if let Cow::Owned(name) = name {
+            m.name = name.into();
+        }
+        for s in &mut m.samples {
+            if s.labels.iter().any(|(k, _)| escape_name(k, scheme) != k) {
+                s.labels = s
+                    .labels
+                    .iter()
+                    .map(|(k, v)| (escape_name(k, scheme).into_owned(), v.to_string()))
+                    .collect();
+            }
+        }
+    }
+}
+
+/// Write a metric or label name, quoted when it isn't a legacy name
+pub(crate) fn write_name<W: Write>(w: &mut W, name: &str) -> fmt::Result {
+    if is_legacy_name(name) {
+        w.write_str(name)
+    } else {
+        write!(w, "\"{}\"", escape_label_value(name))
+    }
+}
+
+/// Write the braces of a sample, with its name first when it has to be quoted
+fn write_labels<W: Write>(w: &mut W, name: Option<&str>, labels: &Labels) -> fmt::Result {
+    if labels.is_empty() && name.is_none() {
+        return Ok(());
+    }
+    w.write_char('{')?;
+    if let Some(name) = name {
+        write_name(w, name)?;
+    }
+    for (i, (k, v)) in labels.iter().enumerate() {
+        if i > 0 || name.is_some() {
+            w.write_char(',')?;
+        }
+        write_name(w, k)?;
+        write!(w, "=\"{}\"", escape_label_value(v))?;
+    }
+    w.write_char('}')
+}
+
+/// Write the name and the labels of a sample, a name that isn't a legacy name is quoted inside the braces
+fn write_series<W: Write>(w: &mut W, name: &str, labels: &Labels) -> fmt::Result {
+    if is_legacy_name(name) {
+        w.write_str(name)?;
+        write_labels(w, None, labels)
+    } else {
+        write_labels(w, Some(name), labels)
+    }
+}
+
+/// Write a sample line (including its line break), labels are written sorted by name
+pub fn write_sample<W: Write>(w: &mut W, name: &str, s: &Sample) -> fmt::Result {
+    write_series(w, name, &s.labels)?;
+    write!(w, " {}", format_value(s.value))?;
+    if let Some(ts) = s.timestamp {
+        write!(w, " {}", ts)?;
+    }
+    w.write_char('\n')
+}
+
+/// Write a `# TYPE` line (including its line break)
+pub fn write_type<W: Write>(w: &mut W, name: &str, t: &MetricType) -> fmt::Result {
+    w.write_str("# TYPE ")?;
+    write_name(w, name)?;
+    writeln!(w, " {}", t.as_str())
+}
+
+/// Write a `# HELP` line (including its line break)
+pub fn write_help<W: Write>(w: &mut W, name: &str, doc: &str) -> fmt::Result {
+    w.write_str("# HELP ")?;
+    write_name(w, name)?;
+    if doc.is_empty() {
+        w.write_char('\n')
+    } else {
+        writeln!(w, " {}", escape_help(doc))
+    }
+}
+
+/// Write a line in the exposition format (including its line break)
+pub fn write_line<W: Write>(w: &mut W, line: &Line) -> fmt::Result {
+    match line {
+        Line::Help { name, doc } => write_help(w, name, doc),
+        Line::Type { name, data_type } => write_type(w, name, data_type),
+        Line::Sample { name, sample } => write_sample(w, name, sample),
+        Line::Comment(s) => writeln!(w, "#{}", s),
+        Line::Empty => w.write_char('\n'),
+    }
+}
+
+/// Render a line in the exposition format (including its line break)
+pub fn render_line(line: &Line) -> String {
+    let mut res = String::new();
+    write_line(&mut res, line).expect("Writing to a string never fails");
+    res
+}
+
+/// A metric and the untyped metrics holding the `_bucket`, `_sum` and `_count` samples of a histogram or a summary
+pub(crate) struct Family<'a> {
+    pub metric: &'a Metric,
+    pub children: Vec<&'a Metric>,
+}
+
+/// Group the metrics belonging to the same histogram or summary, the order of the input is kept otherwise
+pub(crate) fn families(metrics: &[Metric]) -> Vec<Family<'_>> {
+    let by_name: HashMap<&str, &Metric> = metrics.iter().map(|m| (&*m.name, m)).collect();
+    let mut res = Vec::new();
+    let mut grouped = HashSet::new();
+    for m in metrics {
+        let suffixes: &[&str] = match m.data_type {
+            MetricType::Histogram => &["_bucket", "_sum", "_count"],
+            MetricType::Summary => &["_sum", "_count"],
+            _ => &[],
+        };
+        let children: Vec<&Metric> = suffixes
+            .iter()
+            .filter_map(|suffix| by_name.get(format!("{}{}", m.name, suffix).as_str()))
+            // Children with a HELP line or without samples stay families so rendering them loses nothing
+            .filter(|c| {
+                c.data_type == MetricType::Untyped && c.help.is_none() && !c.samples.is_empty()
+            })
+            .copied()
+            .collect();
+        grouped.extend(children.iter().map(|c| &*c.name));
+        res.push(Family {
+            metric: m,
+            children,
+        });
+    }
+    res.retain(|f| !grouped.contains(&*f.metric.name));
+    res
+}
+
+/// Write metrics in the text format, samples of histograms and summaries are written right after their `# TYPE` line
+pub fn write_metrics<W: Write>(w: &mut W, metrics: &[Metric]) -> fmt::Result {
+    for f in families(metrics) {
+        let m = f.metric;
+        if let Some(doc) = &m.help {
+            write_help(w, &m.name, doc)?;
+        }
+        if m.data_type != MetricType::Untyped || m.samples.is_empty() {
+            write_type(w, &m.name, &m.data_type)?;
+        }
+        for c in std::iter::once(m).chain(f.children) {
+            for s in &c.samples {
+                write_sample(w, &c.name, s)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Render metrics in the text format
+pub fn render_metrics(metrics: &[Metric]) -> String {
+    let mut res = String::new();
+    write_metrics(&mut res, metrics).expect("Writing to a string never fails");
+    res
+}
+
+/// Render metrics in the text format with their names escaped for scrapers that don't accept quoted names
+pub fn render_metrics_with_escaping(metrics: &[Metric], scheme: EscapingScheme) -> String {
+    if scheme == EscapingScheme::AllowUtf8 {
+        return render_metrics(metrics);
+    }
+    let mut metrics = metrics.to_vec();
+    escape_names(&mut metrics, scheme);
+    render_metrics(&metrics)
+}
+
+fn write_openmetrics_sample<W: Write>(w: &mut W, name: &str, s: &Sample) -> fmt::Result {
+    write_series(w, name, &s.labels)?;
+    write!(w, " {}", format_value(s.value))?;
+    if let Some(ts) = s.timestamp {
+        // OpenMetrics timestamps are in seconds
+        write!(w, " {}", format_value(ts as f64 / 1000f64))?;
+    }
+    w.write_char('\n')
+}
+
+/// Write metrics in the [OpenMetrics](https://openmetrics.io) text format.
+/// Counters are exposed without their `_total` suffix in `# TYPE` lines and with it on samples as required by the format.
+pub fn write_openmetrics<W: Write>(w: &mut W, metrics: &[Metric]) -> fmt::Result {
+    for f in families(metrics) {
+        let m = f.metric;
+        let (name, sample_name) = match m.data_type {
+            MetricType::Counter => {
+                let name = m.name.strip_suffix("_total").unwrap_or(&m.name);
+                (name, format!("{}_total", name))
+            }
+            _ => (&*m.name, m.name.to_string()),
+        };
+        let type_name = match m.data_type {
+            MetricType::Untyped => "unknown",
+            ref t => t.as_str(),
+        };
+        w.write_str("# TYPE ")?;
+        write_name(w, name)?;
+        writeln!(w, " {}", type_name)?;
+        if let Some(doc) = &m.help {
+            write_help(w, name, doc)?;
+        }
+        for s in &m.samples {
+            write_openmetrics_sample(w, &sample_name, s)?;
+        }
+        for c in f.children {
+            for s in &c.samples {
+                write_openmetrics_sample(w, &c.name, s)?;
+            }
+        }
+    }
+    w.write_str("# EOF\n")
+}
+
+/// Render metrics in the OpenMetrics text format
+pub fn render_openmetrics(metrics: &[Metric]) -> String {
+    let mut res = String::new();
+    write_openmetrics(&mut res, metrics).expect("Writing to a string never fails");
+    res
+}
+
+#[test]
+fn test_format_value() {
+    assert_eq!(format_value(1027f64), "1027");
+    assert_eq!(format_value(-0.5), "-0.5");
+    assert_eq!(format_value(0f64), "0");
+    assert_eq!(format_value(1.458255915e9), "1458255915");
+    assert_eq!(format_value(1e-7), "1e-7");
+    assert_eq!(format_value(1.5e300), "1.5e300");
+    assert_eq!(format_value(f64::NAN), "NaN");
+    assert_eq!(format_value(f64::INFINITY), "+Inf");
+    assert_eq!(format_value(f64::NEG_INFINITY), "-Inf");
+}
+
+#[test]
+fn test_render_line() {
+    assert_eq!(
+        render_line(&Line::Sample {
+            name: "msdos_file_access_time_seconds".to_string(),
+            sample: Sample::new(
+                1.458255915e9,
+                None,
+                vec![
+                    "path",
+                    "C:\\DIR\\FILE.TXT",
+                    "error",
+                    "Cannot find file:\n\"FILE.TXT\""
+                ]
+            ),
+        }),
+        "msdos_file_access_time_seconds{error=\"Cannot find file:\\n\\\"FILE.TXT\\\"\",path=\"C:\\\\DIR\\\\FILE.TXT\"} 1458255915\n"
+    );
+    assert_eq!(
+        render_line(&Line::Sample {
+            name: "something_weird".to_string(),
+            sample: Sample::new(f64::INFINITY, Some(-3982045), vec![]),
+        }),
+        "something_weird +Inf -3982045\n"
+    );
+    assert_eq!(
+        render_line(&Line::Help {
+            name: "foo".to_string(),
+            doc: "Some\\help\non two lines".to_string(),
+        }),
+        "# HELP foo Some\\\\help\\non two lines\n"
+    );
+    assert_eq!(
+        render_line(&Line::Type {
+            name: "foo".to_string(),
+            data_type: MetricType::Counter,
+        }),
+        "# TYPE foo counter\n"
+    );
+    assert_eq!(
+        render_line(&Line::Comment(" A comment".to_string())),
+        "# A comment\n"
+    );
+    assert_eq!(render_line(&Line::Empty), "\n");
+}
+
+#[test]
+fn test_render_quoted_names() {
+    let sample = Sample::new(1f64, None, vec!["a.b", "x", "c", "y\""]);
+    assert_eq!(
+        render_line(&Line::Sample {
+            name: "my.metric".to_string(),
+            sample: sample.clone(),
+        }),
+        "{\"my.metric\",\"a.b\"=\"x\",c=\"y\\\"\"} 1\n"
+    );
+    assert_eq!(
+        render_line(&Line::Sample {
+            name: "\"quoted\"".to_string(),
+            sample: Sample::new(1f64, None, vec![]),
+        }),
+        "{\"\\\"quoted\\\"\"} 1\n"
+    );
+    assert_eq!(
+        render_line(&Line::Sample {
+            name: "legacy".to_string(),
+            sample,
+        }),
+        "legacy{\"a.b\"=\"x\",c=\"y\\\"\"} 1\n"
+    );
+    assert_eq!(
+        render_line(&Line::Type {
+            name: "my.metric".to_string(),
+            data_type: MetricType::Gauge,
+        }),
+        "# TYPE \"my.metric\" gauge\n"
+    );
+    assert_eq!(
+        render_line(&Line::Help {
+            name: "my.metric".to_string(),
+            doc: String::new(),
+        }),
+        "# HELP \"my.metric\"\n"
+    );
+}
+
+#[test]
+fn test_escape_name() {
+    // The test cases of Prometheus' `EscapeName`
+    let cases = [
+        (
+            "no:escaping_required",
+            "no:escaping_required",
+            "no:escaping__required",
+            "no:escaping_required",
+        ),
+        (
+            "mysystem.prod.west.cpu.load",
+            "mysystem_prod_west_cpu_load",
+            "mysystem_dot_prod_dot_west_dot_cpu_dot_load",
+            "U__mysystem_2e_prod_2e_west_2e_cpu_2e_load",
+        ),
+        (
+            "mysystem.prod.west.cpu.load_total",
+            "mysystem_prod_west_cpu_load_total",
+            "mysystem_dot_prod_dot_west_dot_cpu_dot_load__total",
+            "U__mysystem_2e_prod_2e_west_2e_cpu_2e_load__total",
+        ),
+        (
+            "http.status:sum",
+            "http_status:sum",
+            "http_dot_status:sum",
+            "U__http_2e_status:sum",
+        ),
+        (
+            "label with 😱",
+            "label_with__",
+            "label__with____",
+            "U__label_20_with_20__1f631_",
+        ),
+        ("蓝绿", "__", "____", "U___84dd__7eff_"),
+        ("0leading", "_leading", "__leading", "U___30_leading"),
+        ("", "", "", ""),
+    ];
+    for (name, underscores, dots, values) in cases.iter() {
+        assert_eq!(escape_name(name, EscapingScheme::Underscores), *underscores);
+        assert_eq!(escape_name(name, EscapingScheme::Dots), *dots);
+        assert_eq!(escape_name(name, EscapingScheme::Values), *values);
+        assert_eq!(escape_name(name, EscapingScheme::AllowUtf8), *name);
+    }
+
+    let metrics =
+        crate::parse_complete("# TYPE \"a.b\" counter\n{\"a.b\",\"c d\"=\"x\",e=\"y\"} 1\n")
+            .unwrap();
+    assert_eq!(
+        render_metrics_with_escaping(&metrics, EscapingScheme::Underscores),
+        "# TYPE a_b counter\na_b{c_d=\"x\",e=\"y\"} 1\n"
+    );
+    assert_eq!(
+        render_metrics_with_escaping(&metrics, EscapingScheme::Values),
+        "# TYPE U__a_2e_b counter\nU__a_2e_b{U__c_20_d=\"x\",e=\"y\"} 1\n"
+    );
+    assert_eq!(
+        render_metrics_with_escaping(&metrics, EscapingScheme::AllowUtf8),
+        render_metrics(&metrics)
+    );
+
+    // The samples of a family keep its escaped name as a prefix
+    let metrics = crate::parse_complete(
+        r#"# TYPE "my.hist" histogram
+{"my.hist_bucket",le="+Inf"} 2
+{"my.hist_sum"} 3
+{"my.hist_count"} 2
+# TYPE http_requests_total counter
+http_requests_total 1
+# TYPE "a.b_total" counter
+{"a.b_total"} 1
+"#,
+    )
+    .unwrap();
+    let names = |scheme| {
+        let mut metrics = metrics.clone();
+        escape_names(&mut metrics, scheme);
+        metrics
+            .iter()
+            .map(|m| m.name.to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        names(EscapingScheme::Dots),
+        vec![
+            "a_dot_b_total",
+            "http__requests_total",
+            "my_dot_hist",
+            "my_dot_hist_bucket",
+            "my_dot_hist_count",
+            "my_dot_hist_sum"
+        ]
+    );
+    assert_eq!(
+        names(EscapingScheme::Underscores),
+        vec![
+            "a_b_total",
+            "http_requests_total",
+            "my_hist",
+            "my_hist_bucket",
+            "my_hist_count",
+            "my_hist_sum"
+        ]
+    );
+    assert_eq!(
+        names(EscapingScheme::Values),
+        vec![
+            "U__a_2e_b__total",
+            "http_requests_total",
+            "U__my_2e_hist",
+            "U__my_2e_hist__bucket",
+            "U__my_2e_hist__count",
+            "U__my_2e_hist__sum"
+        ]
+    );
+    let mut escaped = metrics.clone();
+    escape_names(&mut escaped, EscapingScheme::Dots);
+    assert_eq!(
+        render_metrics(&escaped),
+        "# TYPE a_dot_b_total counter\na_dot_b_total 1\n# TYPE http__requests_total counter\nhttp__requests_total 1\n# TYPE my_dot_hist histogram\nmy_dot_hist_bucket{le=\"+Inf\"} 2\nmy_dot_hist_sum 3\nmy_dot_hist_count 2\n"
+    );
+}
+
+#[cfg(test)]
+const HISTOGRAM: &str = r#"# HELP http_request_duration_seconds A histogram of the request duration.
+# TYPE http_request_duration_seconds histogram
+http_request_duration_seconds_bucket{le="0.05"} 24054
+http_request_duration_seconds_bucket{le="+Inf"} 144320
+http_request_duration_seconds_sum 53423
+http_request_duration_seconds_count 144320
+# TYPE http_requests_total counter
+http_requests_total{code="200",method="post"} 1027 1395066363000
+rpc_duration_seconds_count 2693
+"#;
+
+#[test]
+fn test_render_metrics() {
+    let metrics = crate::parse_complete(HISTOGRAM).unwrap();
+    assert_eq!(render_metrics(&metrics), HISTOGRAM);
+
+    // A child with a HELP line or without samples isn't grouped in its family
+    let input = "# TYPE a summary\na 1\n# TYPE a_count untyped\n# HELP a_sum doc\na_sum 2\n";
+    let metrics = crate::parse_complete(input).unwrap();
+    assert_eq!(
+        crate::parse_complete(&render_metrics(&metrics)).unwrap(),
+        metrics
+    );
+}
+
+#[test]
+fn test_render_openmetrics() {
+    let metrics = crate::parse_complete(HISTOGRAM).unwrap();
+    assert_eq!(
+        render_openmetrics(&metrics),
+        r#"# TYPE http_request_duration_seconds histogram
+# HELP http_request_duration_seconds A histogram of the request duration.
+http_request_duration_seconds_bucket{le="0.05"} 24054
+http_request_duration_seconds_bucket{le="+Inf"} 144320
+http_request_duration_seconds_sum 53423
+http_request_duration_seconds_count 144320
+# TYPE http_requests counter
+http_requests_total{code="200",method="post"} 1027 1395066363
+# TYPE rpc_duration_seconds_count unknown
+rpc_duration_seconds_count 2693
+# EOF
+"#
+    );
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    #[test]
+    fn test_render_round_trip(mut metrics in proptest::collection::vec(proptest::arbitrary::any::<Metric>(), 0..6)) {
+        // Parsing merges metrics with the same name and sorts them
+        metrics.sort_by(|a, b| a.name.cmp(&b.name));
+        metrics.dedup_by(|a, b| a.name == b.name);
+        let rendered = render_metrics(&metrics);
+        let parsed = crate::parse_complete(&rendered);
+        proptest::prop_assert!(parsed.is_ok(), "{:?} in\n{}", parsed, rendered);
+        // NaN values aren't equal to themselves
+        proptest::prop_assert_eq!(format!("{:?}", parsed.unwrap()), format!("{:?}", metrics), "{}", rendered);
+    }
+}
@@ -1,101 +1,205 @@
-use crate::common::token_parser;
+use crate::common::{name_parser, quoted_name_parser, token_parser, unescape_quoted};
 #[cfg(test)]
 use assert_approx_eq::assert_approx_eq;
 use nom::branch::alt;
-use nom::bytes::complete::{is_not, tag};
-use nom::character::complete::{char, line_ending, none_of, space0, space1};
-use nom::combinator::{map, map_opt, map_res, opt, value};
+use nom::bytes::complete::{escaped, is_not, tag};
+use nom::character::complete::{char, line_ending, none_of, one_of, space0, space1};
+use nom::combinator::{map, map_opt, map_res, opt, recognize, value};
 #[cfg(test)]
 use nom::error::ErrorKind;
-use nom::multi::{fold_many0, separated_list};
-use nom::sequence::{delimited, preceded, separated_pair, terminated, tuple};
+use nom::multi::separated_nonempty_list;
+use nom::sequence::{delimited, pair, preceded, separated_pair, terminated, tuple};
 #[cfg(test)]
 use nom::Err::Error;
 use nom::IResult;
-use std::collections::HashMap;
+use std::borrow::Cow;
 
 #[derive(Debug, PartialEq)]
 pub struct SampleEntry<'a> {
-    pub name: &'a str,
-    pub labels: HashMap<&'a str, String>,
+    pub name: Cow<'a, str>,
+    pub labels: RawLabels<'a>,
     pub value: f64,
     pub timestamp_ms: Option<i64>,
 }
 
+/// The labels of a sample as written between the braces, already validated by the parser.
+/// They're iterated in the order of the input including duplicated names, building `Labels` from them keeps the last value.
+/// Names and values are borrowed from the input unless they contain escape sequences.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RawLabels<'a>(&'a str);
+
+impl<'a> RawLabels<'a> {
+    /// The labels and the quoted metric name if it's written between the braces
+    fn items(self) -> RawItems<'a> {
+        RawItems(self.0)
+    }
+}
+
+impl<'a> IntoIterator for RawLabels<'a> {
+    type Item = (Cow<'a, str>, Cow<'a, str>);
+    type IntoIter = std::iter::FilterMap<RawItems<'a>, LabelItem<'a>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items().filter_map(|(k, v)| v.map(|v| (k, v)))
+    }
+}
+
+type LabelItem<'a> =
+    fn((Cow<'a, str>, Option<Cow<'a, str>>)) -> Option<(Cow<'a, str>, Cow<'a, str>)>;
+
+/// The items between the braces of a sample: labels with their value and a quoted metric name without one
+#[derive(Debug, Clone)]
+pub struct RawItems<'a>(&'a str);
+
+/// The length of the quoted string at the start of `s`, quotes included
+fn quoted_len(s: &str) -> usize {
+    let b = s.as_bytes();
+    let mut end = 1;
+    while b[end] != b'"' {
+        end += if b[end] == b'\\' { 2 } else { 1 };
+    }
+    end + 1
+}
+
+fn unquote(s: &str) -> Cow<'_, str> {
+    let s = &s[1..s.len() - 1];
+    if s.contains('\\') {
+        Cow::Owned(unescape_quoted(s))
+    } else {
+        Cow::Borrowed(s)
+    }
+}
+
+impl<'a> Iterator for RawItems<'a> {
+    type Item = (Cow<'a, str>, Option<Cow<'a, str>>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // The items were validated by the parser, names are either tokens or quoted
+        let s = self.0.trim_start_matches([' ', '\t', ',']);
+        if s.is_empty() {
+            return None;
+        }
+        let (name, rest) = if s.starts_with('"') {
+            let len = quoted_len(s);
+            (unquote(&s[..len]), &s[len..])
+        } else {
+            let len = s.find([' ', '\t', '=']).unwrap_or(s.len());
+            (Cow::Borrowed(&s[..len]), &s[len..])
+        };
+        let rest = rest.trim_start_matches([' ', '\t']);
+        match rest.strip_prefix('=') {
+            Some(rest) => {
+                let rest = rest.trim_start_matches([' ', '\t']);
+                let len = quoted_len(rest);
+                self.0 = &rest[len..];
+                Some((name, Some(unquote(&rest[..len]))))
+            }
+            None => {
+                self.0 = rest;
+                Some((name, None))
+            }
+        }
+    }
+}
+
 fn timestamp_parser(i: &str) -> IResult<&str, i64> {
-    map_opt(is_not("\n "), |x: &str| x.parse::<i64>().ok())(i)
+    map_opt(is_not("\r\n "), |x: &str| x.parse::<i64>().ok())(i)
 }
 
 /// Parse a floating point value similar to [Go's strconv.ParseFloat](https://golang.org/pkg/strconv/#ParseFloat)
 /// It's all explained in the [Prometheus exposition format doc](https://prometheus.io/docs/instrumenting/exposition_formats/#comments-help-text-and-type-information)
-fn value_parser(i: &str) -> IResult<&str, f64> {
+pub fn value_parser(i: &str) -> IResult<&str, f64> {
     alt((
-        value(std::f64::NAN, tag("NaN")),
-        value(std::f64::INFINITY, tag("+Inf")),
-        value(std::f64::NEG_INFINITY, tag("-Inf")),
-        map_res(is_not("\n "), |x: &str| x.parse::<f64>()),
+        value(f64::NAN, tag("NaN")),
+        value(f64::INFINITY, tag("+Inf")),
+        value(f64::NEG_INFINITY, tag("-Inf")),
+        map_res(is_not("\r\n "), |x: &str| x.parse::<f64>()),
     ))(i)
 }
 
-fn tag_value_parser(i: &str) -> IResult<&str, String> {
-    delimited(
+/// Parse a complete string as a float the same way sample values are parsed
+pub fn parse_float(s: &str) -> Option<f64> {
+    match value_parser(s) {
+        Ok(("", v)) => Some(v),
+        _ => None,
+    }
+}
+
+/// Recognize a quoted label value, it's only unescaped when the labels are iterated
+fn tag_value_parser(i: &str) -> IResult<&str, &str> {
+    recognize(delimited(
         char('\"'),
-        fold_many0(
-            alt((
-                preceded(
-                    char('\\'),
-                    alt((
-                        value('\n', char('n')),
-                        value('\"', char('\"')),
-                        value('\\', char('\\')),
-                    )),
-                ),
-                none_of("\n\"\\"),
-            )),
-            String::new(),
-            |mut acc, item| {
-                acc.push(item);
-                acc
-            },
-        ),
+        opt(escaped(none_of("\n\"\\"), '\\', one_of("n\"\\"))),
         char('\"'),
+    ))(i)
+}
+
+fn label_parser(i: &str) -> IResult<&str, (&str, &str)> {
+    separated_pair(
+        recognize(name_parser),
+        tuple((space0, char('='), space0)),
+        tag_value_parser,
     )(i)
 }
 
-fn labels_parser(i: &str) -> IResult<&str, HashMap<&str, String>> {
-    let list_parser = terminated(
-        separated_list(
-            char(','),
-            separated_pair(token_parser, char('='), tag_value_parser),
-        ),
-        opt(char(',')),
-    );
-    let list_parser = map(
-        list_parser,
-        |l: Vec<(&str, String)>| -> HashMap<&str, String> { l.into_iter().collect() },
-    );
+/// Parse comma separated items, blanks are allowed around the commas and a trailing comma is allowed after an item.
+/// Like in Prometheus a comma alone isn't a list.
+fn list_parser<'a, O, F>(item: F) -> impl Fn(&'a str) -> IResult<&'a str, Vec<O>>
+where
+    F: Fn(&'a str) -> IResult<&'a str, O>,
+{
+    map(
+        opt(terminated(
+            separated_nonempty_list(tuple((space0, char(','), space0)), item),
+            opt(pair(space0, char(','))),
+        )),
+        Option::unwrap_or_default,
+    )
+}
 
+/// Parse the braces of a sample, blanks are allowed after `{` and before `}` like in Prometheus' parser
+pub fn labels_parser(i: &str) -> IResult<&str, RawLabels<'_>> {
     map(
         opt(delimited(
-            preceded(space0, char('{')),
-            list_parser,
-            char('}'),
+            tuple((space0, char('{'), space0)),
+            recognize(list_parser(map(label_parser, |_| ()))),
+            pair(space0, char('}')),
         )),
-        |v| v.unwrap_or(HashMap::new()),
+        |l| RawLabels(l.unwrap_or_default()),
     )(i)
 }
 
-/// Parse a metric sample according to the [exposition format](https://prometheus.io/docs/instrumenting/exposition_formats/#text-format-example).
-///
-/// # Arguments
-///
-/// `i` - A input string to parse
-///
-pub fn parse_sample(i: &str) -> IResult<&str, SampleEntry> {
-    let (input, (name, labels, value, timestamp_ms)) = terminated(
+/// Parse a quoted metric name written between the braces among the labels: `{"my.metric",a="b"}`.
+/// Like in Prometheus the name can be anywhere in the list but it's usually first.
+fn braced_name_parser(i: &str) -> IResult<&str, (Cow<'_, str>, RawLabels<'_>)> {
+    // A quoted string followed by `=` is a label name
+    let item = alt((map(label_parser, |_| ()), map(quoted_name_parser, |_| ())));
+    map_opt(
+        delimited(
+            pair(char('{'), space0),
+            recognize(list_parser(item)),
+            pair(space0, char('}')),
+        ),
+        |items| {
+            let mut names = RawItems(items).filter_map(|(k, v)| match v {
+                Some(_) => None,
+                None => Some(k),
+            });
+            match (names.next(), names.next()) {
+                (Some(name), None) => Some((name, RawLabels(items))),
+                _ => None,
+            }
+        },
+    )(i)
+}
+
+fn sample_parser(i: &str) -> IResult<&str, SampleEntry<'_>> {
+    let (input, ((name, labels), value, timestamp_ms)) = terminated(
         tuple((
-            token_parser,
-            labels_parser,
+            alt((
+                pair(map(token_parser, Cow::Borrowed), labels_parser),
+                braced_name_parser,
+            )),
             preceded(space1, value_parser),
             opt(preceded(space1, timestamp_parser)),
         )),
@@ -113,6 +217,134 @@ pub fn parse_sample(i: &str) -> IResult<&str, SampleEntry> {
     ))
 }
 
+fn is_name_start(b: u8) -> bool {
+    b.is_ascii_alphabetic() || b == b'_' || b == b':'
+}
+
+/// The length of the ASCII name at the start of `b`, 0 when there's none
+fn ascii_name_len(b: &[u8]) -> usize {
+    match b.first() {
+        Some(&c) if is_name_start(c) => {
+            1 + b[1..]
+                .iter()
+                .take_while(|&&c| is_name_start(c) || c.is_ascii_digit())
+                .count()
+        }
+        _ => 0,
+    }
+}
+
+/// The length of the spaces and tabs at the start of `b`
+fn spaces_len(b: &[u8]) -> usize {
+    b.iter().take_while(|&&c| c == b' ' || c == b'\t').count()
+}
+
+/// The length of the value or timestamp at the start of `b`, it ends like in `value_parser`
+fn number_len(b: &[u8]) -> usize {
+    b.iter()
+        .position(|&c| c == b'\r' || c == b'\n' || c == b' ')
+        .unwrap_or(b.len())
+}
+
+/// Parse the common samples without nom: ASCII names and plain decimal values.
+/// Anything else returns `None` and is left to `sample_parser`, when a sample is returned it's the one `sample_parser` returns.
+pub fn fast_sample(i: &str) -> Option<(&str, SampleEntry<'_>)> {
+    let b = i.as_bytes();
+    let mut pos = ascii_name_len(b);
+    if pos == 0 {
+        return None;
+    }
+    let name = &i[..pos];
+    let mut labels = "";
+    if b.get(pos) == Some(&b'{') {
+        pos += 1;
+        let first = pos;
+        while b.get(pos) != Some(&b'}') {
+            let len = ascii_name_len(&b[pos..]);
+            if len == 0 || b.get(pos + len..pos + len + 2) != Some(b"=\"") {
+                return None;
+            }
+            pos += len + 2;
+            loop {
+                match b.get(pos)? {
+                    b'"' => break,
+                    b'\\' if matches!(b.get(pos + 1), Some(b'n') | Some(b'"') | Some(b'\\')) => {
+                        pos += 2
+                    }
+                    b'\\' | b'\n' => return None,
+                    _ => pos += 1,
+                }
+            }
+            pos += 1;
+            match b.get(pos) {
+                Some(b',') => pos += 1,
+                Some(b'}') => {}
+                _ => return None,
+            }
+        }
+        labels = &i[first..pos];
+        pos += 1;
+    }
+
+    let spaces = spaces_len(&b[pos..]);
+    let len = number_len(&b[pos + spaces..]);
+    if spaces == 0 || len == 0 {
+        return None;
+    }
+    pos += spaces;
+    let value = &b[pos..pos + len];
+    if !value.iter().all(|c| b"0123456789.+-eE".contains(c)) {
+        return None;
+    }
+    let value = i[pos..pos + len].parse::<f64>().ok()?;
+    pos += len;
+
+    let mut timestamp_ms = None;
+    let spaces = spaces_len(&b[pos..]);
+    if spaces > 0 {
+        pos += spaces;
+        let len = number_len(&b[pos..]);
+        if len == 0
+            || !b[pos..pos + len]
+                .iter()
+                .all(|&c| c.is_ascii_digit() || c == b'-')
+        {
+            return None;
+        }
+        timestamp_ms = Some(i[pos..pos + len].parse::<i64>().ok()?);
+        pos += len;
+    }
+
+    pos += match &b[pos..] {
+        [b'\n', ..] => 1,
+        [b'\r', b'\n', ..] => 2,
+        _ => return None,
+    };
+    Some((
+        &i[pos..],
+        SampleEntry {
+            name: Cow::Borrowed(name),
+            labels: RawLabels(labels),
+            value,
+            timestamp_ms,
+        },
+    ))
+}
+
+/// Parse a metric sample according to the [exposition format](https://prometheus.io/docs/instrumenting/exposition_formats/#text-format-example).
+/// Common samples are parsed by hand, the others with nom.
+///
+/// # Arguments
+///
+/// `i` - A input string to parse
+///
+pub fn parse_sample(i: &str) -> IResult<&str, SampleEntry<'_>> {
+    match fast_sample(i) {
+        Some(res) => Ok(res),
+        None => sample_parser(i),
+    }
+}
+
 #[test]
 fn test_timestamp_parser() {
     assert_eq!(timestamp_parser(""), Err(Error(("", ErrorKind::IsNot))));
@@ -123,68 +355,106 @@ fn test_timestamp_parser() {
     assert_eq!(timestamp_parser("1234"), Ok(("", 1234)));
     assert_eq!(timestamp_parser("1234 foo"), Ok((" foo", 1234)));
     assert_eq!(timestamp_parser("-1234 foo"), Ok((" foo", -1234)));
+    assert_eq!(timestamp_parser("1234\r\n"), Ok(("\r\n", 1234)));
 }
 
 #[test]
 fn test_tag_value_parser() {
+    let assert_value = |s, left, value: &str| {
+        let (rest, raw) = tag_value_parser(s).unwrap();
+        assert_eq!((rest, unquote(raw)), (left, Cow::Borrowed(value)));
+    };
     // Empty string
-    assert_eq!(tag_value_parser("\"\""), Ok(("", "".to_string())));
+    assert_value("\"\"", "", "");
     // Simple string
-    assert_eq!(tag_value_parser("\"abc\""), Ok(("", "abc".to_string())));
+    assert_value("\"abc\"", "", "abc");
     // Doesn't consume trailing
-    assert_eq!(tag_value_parser("\"abc\"aa"), Ok(("aa", "abc".to_string())));
+    assert_value("\"abc\"aa", "aa", "abc");
     // Unescapes escaped "
-    assert_eq!(tag_value_parser("\"\\\"\""), Ok(("", "\"".to_string())));
+    assert_value("\"\\\"\"", "", "\"");
     // Unescapes escaped line break
-    assert_eq!(tag_value_parser("\"\\n\""), Ok(("", "\n".to_string())));
+    assert_value("\"\\n\"", "", "\n");
     // Unescapes escaped \
-    assert_eq!(tag_value_parser("\"\\\\\""), Ok(("", "\\".to_string())));
+    assert_value("\"\\\\\"", "", "\\");
     // Fails with unescaped line break
     assert_eq!(
         tag_value_parser("\"\n\""),
         Err(Error(("\n\"", ErrorKind::Char)))
     );
+    // Fails with unknown escape sequences
+    assert!(tag_value_parser("\"\\t\"").is_err());
     // Complex value from the doc
-    assert_eq!(
-        tag_value_parser("\"C:\\\\DIR\\\\FILE.TXT\""),
-        Ok(("", "C:\\DIR\\FILE.TXT".to_string()))
-    );
+    assert_value("\"C:\\\\DIR\\\\FILE.TXT\"", "", "C:\\DIR\\FILE.TXT");
     // Complex value from the doc
-    assert_eq!(
-        tag_value_parser("\"Cannot find file:\\n\\\"FILE.TXT\\\"\""),
-        Ok(("", "Cannot find file:\n\"FILE.TXT\"".to_string()))
+    assert_value(
+        "\"Cannot find file:\\n\\\"FILE.TXT\\\"\"",
+        "",
+        "Cannot find file:\n\"FILE.TXT\"",
     );
 }
 
 #[cfg(test)]
-fn vec_to_hashmap<'a>(vec: Vec<(&'a str, &'a str)>) -> HashMap<&'a str, String> {
-    vec.into_iter().map(|(a, b)| (a, b.to_string())).collect()
+fn label_vec(labels: RawLabels) -> Vec<(Cow<str>, Cow<str>)> {
+    labels.into_iter().collect()
+}
+
+#[cfg(test)]
+fn borrowed_vec<'a>(vec: Vec<(&'a str, &'a str)>) -> Vec<(Cow<'a, str>, Cow<'a, str>)> {
+    vec.into_iter()
+        .map(|(a, b)| (Cow::Borrowed(a), Cow::Borrowed(b)))
+        .collect()
 }
 
 #[test]
 fn test_labels_parser() {
     let assert_labels = |s, vec: Vec<(&str, &str)>| {
-        assert_eq!(labels_parser(s), Ok(("", vec_to_hashmap(vec))));
+        let (rest, labels) = labels_parser(s).unwrap();
+        assert_eq!(
+            (rest, label_vec(labels)),
+            ("", borrowed_vec(vec)),
+            "{:?}",
+            s
+        );
     };
     // Empty space doesn't consume
-    assert_eq!(labels_parser(" "), Ok((" ", HashMap::new())));
+    assert_eq!(labels_parser(" "), Ok((" ", RawLabels::default())));
 
     // Empty labels with prefixed space
-    assert_eq!(labels_parser(" {}"), Ok(("", HashMap::new())));
+    assert_eq!(labels_parser(" {}"), Ok(("", RawLabels::default())));
     // Empty labels
-    assert_eq!(labels_parser("{}"), Ok(("", HashMap::new())));
+    assert_eq!(labels_parser("{}"), Ok(("", RawLabels::default())));
     // Empty string
-    assert_eq!(labels_parser(""), Ok(("", HashMap::new())));
+    assert_eq!(labels_parser(""), Ok(("", RawLabels::default())));
     // Prefixed
-    assert_eq!(labels_parser("d{}"), Ok(("d{}", HashMap::new())));
+    assert_eq!(labels_parser("d{}"), Ok(("d{}", RawLabels::default())));
     // No quotes on label
-    assert_eq!(labels_parser("{he=e}"), Ok(("{he=e}", HashMap::new())));
+    assert_eq!(
+        labels_parser("{he=e}"),
+        Ok(("{he=e}", RawLabels::default()))
+    );
     // A simple label
     assert_labels("{hello=\"how are you?\"}", vec![("hello", "how are you?")]);
     // Multiple labels
     assert_labels("{a=\"b\",c=\"d\"}", vec![("a", "b"), ("c", "d")]);
     // When there's a trailing comma
     assert_labels("{a=\"b\",c=\"d\",}", vec![("a", "b"), ("c", "d")]);
+    // A comma alone isn't a list
+    assert_eq!(labels_parser("{,}"), Ok(("{,}", RawLabels::default())));
+    assert!(parse_sample("a{,} 1\n").is_err());
+    assert!(parse_sample("a{ , } 1\n").is_err());
+    assert!(parse_sample("{\"a\",,} 1\n").is_err());
+    // Blanks inside the braces
+    assert_labels("{ b=\"c\"}", vec![("b", "c")]);
+    assert_labels("{b=\"c\" ,d=\"e\"}", vec![("b", "c"), ("d", "e")]);
+    assert_labels("{b=\"c\", d=\"e\"}", vec![("b", "c"), ("d", "e")]);
+    assert_labels("{\tb = \"c\" , }", vec![("b", "c")]);
+    // Duplicated names are kept in the order of the input
+    assert_labels("{a=\"b\",a=\"c\"}", vec![("a", "b"), ("a", "c")]);
+    // Values with separators and escape sequences
+    assert_labels(
+        "{a=\"b,c=\\\"d\\\"\",\"e f\"=\"}\"}",
+        vec![("a", "b,c=\"d\""), ("e f", "}")],
+    );
 }
 
 #[test]
@@ -192,9 +462,10 @@ fn test_value_parser() {
     assert_eq!(value_parser("1027"), Ok(("", 1027f64)));
     assert_eq!(value_parser("1027 ee"), Ok((" ee", 1027f64)));
     assert_eq!(value_parser("1027\nee"), Ok(("\nee", 1027f64)));
+    assert_eq!(value_parser("1027\r\nee"), Ok(("\r\nee", 1027f64)));
     assert_eq!(value_parser("ee"), Err(Error(("ee", ErrorKind::MapRes))));
-    assert_eq!(value_parser("+Inf"), Ok(("", std::f64::INFINITY)));
-    assert_eq!(value_parser("-Inf"), Ok(("", std::f64::NEG_INFINITY)));
+    assert_eq!(value_parser("+Inf"), Ok(("", f64::INFINITY)));
+    assert_eq!(value_parser("-Inf"), Ok(("", f64::NEG_INFINITY)));
     assert!(value_parser("NaN").unwrap().1.is_nan());
     assert_approx_eq!(value_parser("2.00").unwrap().1, 2f64);
     assert_approx_eq!(value_parser("1e-3").unwrap().1, 0.001);
@@ -212,8 +483,8 @@ fn assert_sample(
 ) {
     assert_eq!(res.name, name, "sample name is different {:?}", res);
     assert_eq!(
-        res.labels,
-        vec_to_hashmap(labels),
+        label_vec(res.labels),
+        borrowed_vec(labels),
         "labels are different {:?}",
         res
     );
@@ -283,7 +554,7 @@ fn test_parse_sample_parser() {
         "",
         "something_weird",
         vec![("problem", "division by zero")],
-        std::f64::INFINITY,
+        f64::INFINITY,
         Some(-3982045),
     );
     assert_sample_parser(
@@ -417,6 +688,16 @@ fn test_parse_sample_parser() {
         None,
     );
 
+    // With CRLF line endings
+    assert_sample_parser(
+        "http_requests_total{code=\"200\"} 1027 1395066363000\r\nfoo",
+        "foo",
+        "http_requests_total",
+        vec![("code", "200")],
+        1027f64,
+        Some(1395066363000),
+    );
+
     // With space before labels
     assert_sample_parser(
         "test {a=\"b\"} 0\n",
@@ -427,6 +708,21 @@ fn test_parse_sample_parser() {
         None,
     );
 
+    // With blanks inside the braces
+    for s in &[
+        "a{ b=\"c\"} 1\n",
+        "a{b=\"c\" } 1\n",
+        "a{b=\"c\" ,d=\"e\"} 1\n",
+        "a{b=\"c\", d=\"e\"} 1\n",
+    ] {
+        let labels = if s.contains('d') {
+            vec![("b", "c"), ("d", "e")]
+        } else {
+            vec![("b", "c")]
+        };
+        assert_sample_parser(s, "", "a", labels, 1f64, None);
+    }
+
     // Fails when there's just a metric name
     assert_eq!(
         parse_sample("metric_without_timestamp_and_labels\n"),
@@ -443,3 +739,105 @@ fn test_parse_sample_parser() {
         Err(Error(("", ErrorKind::CrLf)))
     );
 }
+
+#[test]
+fn test_parse_quoted_names() {
+    assert_sample_parser(
+        "{\"my.metric\", \"label.name\" = \"v\" ,a=\"b\",} 1\n",
+        "",
+        "my.metric",
+        vec![("label.name", "v"), ("a", "b")],
+        1f64,
+        None,
+    );
+    // The name can be anywhere between the braces
+    assert_sample_parser(
+        "{q=\"0.9\",\"http.status\",a=\"b\"} 8.3835e-05 10\n",
+        "",
+        "http.status",
+        vec![("q", "0.9"), ("a", "b")],
+        8.3835e-05,
+        Some(10),
+    );
+    assert_sample_parser("{\"a\\\"b\\nc\"} 1\n", "", "a\"b\nc", vec![], 1f64, None);
+    assert_sample_parser(
+        "legacy{ \"é 2\"=\"x\" } 1\n",
+        "",
+        "legacy",
+        vec![("é 2", "x")],
+        1f64,
+        None,
+    );
+    // Exactly one name, quoted and not empty
+    assert!(parse_sample("{} 1\n").is_err());
+    assert!(parse_sample("{\"a\",\"b\"} 1\n").is_err());
+    assert!(parse_sample("{a} 1\n").is_err());
+    assert!(parse_sample("{\"\"} 1\n").is_err());
+    assert!(parse_sample("a{\"b\"} 1\n").is_err());
+    assert!(parse_sample("\"a\" 1\n").is_err());
+}
+
+/// A sample as comparable values, `f64` and `RawLabels` don't compare as needed
+#[cfg(test)]
+type Normalized<'a> = (
+    &'a str,
+    Cow<'a, str>,
+    Vec<(Cow<'a, str>, Cow<'a, str>)>,
+    u64,
+    Option<i64>,
+);
+
+#[cfg(test)]
+fn normalize<'a>(
+    res: IResult<&'a str, SampleEntry<'a>>,
+) -> Result<Normalized<'a>, nom::Err<(&'a str, ErrorKind)>> {
+    res.map(|(rest, s)| {
+        let mut labels: Vec<_> = s.labels.into_iter().collect();
+        labels.sort();
+        (rest, s.name, labels, s.value.to_bits(), s.timestamp_ms)
+    })
+}
+
+#[test]
+fn test_fast_sample() {
+    let fast = |s| normalize(fast_sample(s).ok_or(Error((s, ErrorKind::Not))));
+    for s in &[
+        "a 1\n",
+        "a_b:c{d=\"e\",f=\"\"} -1.5e3 -12\r\nrest",
+        "a{b=\"c\",} 1 \t2\n",
+        "a{b=\"c\",b=\"d\"} 1\n",
+        "a{b=\"é\"} 01\n",
+        "a{b=\"\\n\\\"\\\\\"} 1\n",
+    ] {
+        assert_eq!(fast(s), normalize(sample_parser(s)), "{:?}", s);
+    }
+    for s in &[
+        "a {b=\"c\"} 1\n",
+        "a{b=\"\\t\"} 1\n",
+        "a{b=\"\\\"} 1\n",
+        "a{,} 1\n",
+        "é 1\n",
+        "a NaN\n",
+        "a +Inf\n",
+        "a 1 \n",
+        "a 1 1.5\n",
+        "a 1\t\n",
+        "a 1",
+        "a{b=\"c\"d=\"e\"} 1\n",
+    ] {
+        assert!(fast_sample(s).is_none(), "{:?}", s);
+    }
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    #[test]
+    fn test_fast_sample_equivalence(s in "[a-c_:é0-9]{0,4}( ?\\{([a-c_é]{1,2}=\"[a-c\\\\\"n ,{}=é]{0,3}\",?){0,3},?\\}| ?\\{ ?, ?\\})?[ \t]{0,2}(-?[0-9.eE+a]{0,5}|NaN|\\+Inf|-Inf)([ \t]{1,2}-?[0-9.a]{0,3})?[ \t]?(\r?\n)?a?") {
+        proptest::prop_assert_eq!(normalize(parse_sample(&s)), normalize(sample_parser(&s)));
+    }
+
+    #[test]
+    fn test_fast_sample_common(s in "[a-z_:][a-z0-9_:]{0,8}(\\{([a-z_]{1,3}=\"[a-z0-9 .+]{0,5}\",){0,3}\\})? -?[0-9]{1,5}(\\.[0-9]{1,3})?(e-?[0-9]{1,2})?( -?[0-9]{1,13})?\r?\n") {
+        proptest::prop_assert!(fast_sample(&s).is_some());
+    }
+}
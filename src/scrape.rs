@@ -0,0 +1,251 @@
+use crate::compression::{decoder, Encoding};
+use crate::parse_reader_with_options;
+use crate::target::TargetLabels;
+#[cfg(test)]
+use crate::types::Limit;
+use crate::types::{Err, Metric, MetricType, ParseOptions, Sample};
+use std::io::{self, BufReader, Read};
+use std::time::{Duration, Instant};
+
+/// The `Accept` header Prometheus sends when it's configured to only scrape the text format
+pub const ACCEPT_HEADER: &str = "text/plain;version=0.0.4;q=1,*/*;q=0.1";
+
+/// Options for `scrape`
+#[derive(Debug, Clone)]
+pub struct ScrapeOptions {
+    /// Maximum duration of the whole scrape, it's sent to the target in `X-Prometheus-Scrape-Timeout-Seconds`
+    pub timeout: Duration,
+    /// Add the `scrape_duration_seconds`, `scrape_body_size_bytes` and `scrape_samples_scraped` metrics to the result
+    pub record_scrape_metrics: bool,
+    /// Labels like `job` and `instance` added to every sample, including the scrape metrics
+    pub target: TargetLabels,
+    /// Its `body_size_limit` applies to the uncompressed body
+    pub parse: ParseOptions,
+}
+
+impl Default for ScrapeOptions {
+    fn default() -> Self {
+        ScrapeOptions {
+            timeout: Duration::from_secs(10),
+            record_scrape_metrics: true,
+            target: TargetLabels::default(),
+            parse: ParseOptions::default(),
+        }
+    }
+}
+
+/// Counts the bytes read
+struct CountingReader<R> {
+    inner: R,
+    read: usize,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n;
+        Ok(n)
+    }
+}
+
+/// Only the Prometheus text format is parsed.
+/// OpenMetrics isn't accepted: its timestamps are in seconds and its exemplars and `# EOF` line aren't parsed.
+/// Neither is the protobuf format, `protobuf` can only encode it.
+fn is_prometheus_text(content_type: &str) -> bool {
+    let media_type = content_type.split(';').next().unwrap_or("").trim();
+    media_type.is_empty() || media_type.eq_ignore_ascii_case("text/plain")
+}
+
+fn gauge(name: &str, value: f64) -> Metric {
+    let mut m = Metric::new(name, MetricType::Gauge);
+    m.push_sample(Sample::new(value, None, vec![]));
+    m
+}
+
+/// Scrape a target over HTTP and return the metrics it exposes.
+/// The body is decompressed and parsed while it's received.
+///
+/// Only the Prometheus text format is supported, a target answering in another format is an `Err::Scrape`.
+/// ureq is built without TLS support so only `http://` urls can be scraped.
+pub fn scrape(url: &str, options: &ScrapeOptions) -> Result<Vec<Metric>, Err> {
+    let start = Instant::now();
+    let agent = ureq::AgentBuilder::new().timeout(options.timeout).build();
+    let response = agent
+        .get(url)
+        .set("Accept", ACCEPT_HEADER)
+        .set("Accept-Encoding", "gzip")
+        .set(
+            "X-Prometheus-Scrape-Timeout-Seconds",
+            &options.timeout.as_secs_f64().to_string(),
+        )
+        .call()
+        .map_err(|e| match e {
+            ureq::Error::Status(code, _) => {
+                Err::Scrape(format!("server returned HTTP status {}", code))
+            }
+            ureq::Error::Transport(t) => Err::Scrape(t.to_string()),
+        })?;
+
+    let content_type = response.header("Content-Type").unwrap_or("");
+    if !is_prometheus_text(content_type) {
+        return Err(Err::Scrape(format!(
+            "unsupported content type {}",
+            content_type
+        )));
+    }
+    let content_encoding = response.header("Content-Encoding").unwrap_or("");
+    let encoding = Encoding::from_content_encoding(content_encoding)
+        .ok_or_else(|| Err::Scrape(format!("unsupported content encoding {}", content_encoding)))?;
+
+    let mut body = CountingReader {
+        inner: decoder(BufReader::new(response.into_reader()), encoding)?,
+        read: 0,
+    };
+    let mut res = parse_reader_with_options(BufReader::new(&mut body), &options.parse)?;
+
+    if options.record_scrape_metrics {
+        let samples: usize = res.iter().map(|m| m.samples.len()).sum();
+        res.push(gauge(
+            "scrape_duration_seconds",
+            start.elapsed().as_secs_f64(),
+        ));
+        res.push(gauge("scrape_body_size_bytes", body.read as f64));
+        res.push(gauge("scrape_samples_scraped", samples as f64));
+        res.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+    }
+    options.target.apply(&mut res);
+    Ok(res)
+}
+
+/// Serve a single HTTP response on a local port and return the url to reach it and the request that was received
+#[cfg(test)]
+fn serve_once(headers: &'static str, body: Vec<u8>) -> (String, std::thread::JoinHandle<String>) {
+    use std::io::{BufRead, Write};
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/metrics", listener.local_addr().unwrap());
+    let handle = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut request = String::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line.trim().is_empty() {
+                break;
+            }
+            request.push_str(&line);
+        }
+        let mut stream = reader.into_inner();
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n",
+            headers,
+            body.len()
+        )
+        .unwrap();
+        stream.write_all(&body).unwrap();
+        request
+    });
+    (url, handle)
+}
+
+#[cfg(test)]
+const EXAMPLE: &str = "# TYPE foo counter\nfoo{a=\"b\"} 1\nfoo{a=\"c\"} 2\n";
+
+#[test]
+fn test_scrape() {
+    let (url, server) = serve_once(
+        "Content-Type: text/plain; version=0.0.4\r\n",
+        EXAMPLE.as_bytes().to_vec(),
+    );
+    let options = ScrapeOptions {
+        timeout: Duration::from_millis(1500),
+        ..ScrapeOptions::default()
+    };
+    let res = scrape(&url, &options).unwrap();
+    let request = server.join().unwrap().to_lowercase();
+    assert!(request.contains(&format!("accept: {}", ACCEPT_HEADER)));
+    assert!(request.contains("x-prometheus-scrape-timeout-seconds: 1.5"));
+
+    let names: Vec<&str> = res.iter().map(|m| &*m.name).collect();
+    assert_eq!(
+        names,
+        vec![
+            "foo",
+            "scrape_body_size_bytes",
+            "scrape_duration_seconds",
+            "scrape_samples_scraped"
+        ]
+    );
+    assert_eq!(res[0], crate::parse_complete(EXAMPLE).unwrap()[0]);
+    assert_eq!(res[1].samples[0].value, EXAMPLE.len() as f64);
+    assert_eq!(res[3].samples[0].value, 2f64);
+}
+
+#[test]
+fn test_scrape_gzip() {
+    use std::io::Write;
+    let mut e = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    e.write_all(EXAMPLE.as_bytes()).unwrap();
+    let (url, server) = serve_once(
+        "Content-Type: text/plain\r\nContent-Encoding: gzip\r\n",
+        e.finish().unwrap(),
+    );
+    let options = ScrapeOptions {
+        record_scrape_metrics: false,
+        target: TargetLabels::new(&[("job", "test")], false),
+        ..ScrapeOptions::default()
+    };
+    let res = scrape(&url, &options).unwrap();
+    server.join().unwrap();
+    let mut expected = crate::parse_complete(EXAMPLE).unwrap();
+    options.target.apply(&mut expected);
+    assert_eq!(res, expected);
+    assert!(res[0]
+        .samples
+        .iter()
+        .all(|s| s.labels.get("job") == Some("test")));
+}
+
+#[test]
+fn test_scrape_body_size_limit() {
+    let (url, server) = serve_once("", EXAMPLE.as_bytes().to_vec());
+    let options = ScrapeOptions {
+        parse: ParseOptions {
+            body_size_limit: Some(10),
+            ..ParseOptions::default()
+        },
+        ..ScrapeOptions::default()
+    };
+    assert!(matches!(
+        scrape(&url, &options),
+        Result::Err(Err::LimitExceeded {
+            limit: Limit::BodySize(10),
+            line: 1
+        })
+    ));
+    server.join().unwrap();
+}
+
+#[test]
+fn test_scrape_unsupported_content_type() {
+    let (url, server) = serve_once(
+        "Content-Type: application/vnd.google.protobuf\r\n",
+        vec![0, 1, 2],
+    );
+    assert!(matches!(
+        scrape(&url, &ScrapeOptions::default()),
+        Result::Err(Err::Scrape(_))
+    ));
+    server.join().unwrap();
+
+    let (url, server) = serve_once(
+        "Content-Type: application/openmetrics-text; version=1.0.0\r\n",
+        b"foo 1 1.5\n# EOF\n".to_vec(),
+    );
+    assert!(matches!(
+        scrape(&url, &ScrapeOptions::default()),
+        Result::Err(Err::Scrape(_))
+    ));
+    server.join().unwrap();
+}
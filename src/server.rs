@@ -0,0 +1,252 @@
+use crate::http::{self, Request, Response};
+use crate::protobuf;
+use crate::render::{render_metrics, render_openmetrics};
+use crate::types::Metric;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::{self, Write};
+use std::net::TcpListener;
+
+pub const TEXT_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+pub const OPENMETRICS_CONTENT_TYPE: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// The formats metrics can be served in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    OpenMetrics,
+    Protobuf,
+}
+
+/// A media range of an `Accept` header
+struct MediaRange<'a> {
+    media_type: &'a str,
+    params: Vec<(&'a str, &'a str)>,
+    q: f64,
+}
+
+impl<'a> MediaRange<'a> {
+    fn parse(s: &'a str) -> Self {
+        let mut parts = s.split(';').map(str::trim);
+        let media_type = parts.next().unwrap_or("");
+        let params: Vec<(&str, &str)> = parts
+            .filter_map(|p| p.split_once('='))
+            .map(|(k, v)| (k.trim(), v.trim().trim_matches('"')))
+            .collect();
+        let q = params
+            .iter()
+            .find(|(k, _)| *k == "q")
+            .and_then(|(_, v)| v.parse().ok())
+            .unwrap_or(1f64);
+        MediaRange {
+            media_type,
+            params,
+            q,
+        }
+    }
+
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| *k == name)
+            .map(|(_, v)| *v)
+    }
+
+    fn format(&self) -> Option<Format> {
+        match self.media_type.to_ascii_lowercase().as_str() {
+            "application/vnd.google.protobuf"
+                if self.param("proto") == Some("io.prometheus.client.MetricFamily")
+                    && self.param("encoding") == Some("delimited") =>
+            {
+                Some(Format::Protobuf)
+            }
+            "application/openmetrics-text" => Some(Format::OpenMetrics),
+            "text/plain" | "text/*" | "*/*" => Some(Format::Text),
+            _ => None,
+        }
+    }
+}
+
+impl Format {
+    /// Pick the format from an `Accept` header, the media range with the highest quality wins and ties go to the first one.
+    /// The text format is used when nothing we support is acceptable.
+    pub fn negotiate(accept: Option<&str>) -> Format {
+        let mut ranges: Vec<MediaRange> = accept
+            .unwrap_or("")
+            .split(',')
+            .map(MediaRange::parse)
+            .filter(|r| r.q > 0f64)
+            .collect();
+        // The sort is stable so the order of the header breaks ties
+        ranges.sort_by(|a, b| b.q.partial_cmp(&a.q).unwrap_or(std::cmp::Ordering::Equal));
+        ranges
+            .iter()
+            .find_map(MediaRange::format)
+            .unwrap_or(Format::Text)
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Format::Text => TEXT_CONTENT_TYPE,
+            Format::OpenMetrics => OPENMETRICS_CONTENT_TYPE,
+            Format::Protobuf => protobuf::CONTENT_TYPE,
+        }
+    }
+
+    pub fn render(&self, metrics: &[Metric]) -> Vec<u8> {
+        match self {
+            Format::Text => render_metrics(metrics).into_bytes(),
+            Format::OpenMetrics => render_openmetrics(metrics).into_bytes(),
+            Format::Protobuf => protobuf::encode(metrics),
+        }
+    }
+}
+
+/// Whether an `Accept-Encoding` header accepts gzip
+fn accepts_gzip(accept_encoding: Option<&str>) -> bool {
+    accept_encoding
+        .unwrap_or("")
+        .split(',')
+        .map(MediaRange::parse)
+        .any(|r| r.media_type.eq_ignore_ascii_case("gzip") && r.q > 0f64)
+}
+
+fn gzip(body: &[u8]) -> io::Result<Vec<u8>> {
+    let mut e = GzEncoder::new(Vec::new(), Compression::default());
+    e.write_all(body)?;
+    e.finish()
+}
+
+/// Build the response exposing metrics in the format negotiated with the request headers
+pub fn metrics_response(request: &Request, metrics: &[Metric]) -> Response {
+    let format = Format::negotiate(request.header("Accept"));
+    let body = format.render(metrics);
+    let mut res = if accepts_gzip(request.header("Accept-Encoding")) {
+        match gzip(&body) {
+            Ok(compressed) => {
+                let mut res = Response::new(200, format.content_type(), compressed);
+                res.headers
+                    .push(("Content-Encoding".to_string(), "gzip".to_string()));
+                res
+            }
+            Err(e) => return Response::text(500, &e.to_string()),
+        }
+    } else {
+        Response::new(200, format.content_type(), body)
+    };
+    // Caches must not serve a response negotiated for other request headers
+    res.headers
+        .push(("Vary".to_string(), "Accept, Accept-Encoding".to_string()));
+    res
+}
+
+/// A handler serving the metrics returned by `provider` on `/metrics`, to `GET` and `HEAD` requests
+pub fn handler<P>(provider: P) -> impl Fn(&Request) -> Response
+where
+    P: Fn() -> Vec<Metric>,
+{
+    move |request| match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/metrics") | ("HEAD", "/metrics") => metrics_response(request, &provider()),
+        (_, "/metrics") => Response::text(405, "Method not allowed"),
+        _ => Response::text(404, "Not found"),
+    }
+}
+
+/// Serve the metrics returned by `provider` on `/metrics` forever, `provider` is called on every request
+pub fn serve<P>(listener: TcpListener, provider: P) -> io::Result<()>
+where
+    P: Fn() -> Vec<Metric> + Send + Sync + 'static,
+{
+    http::serve(listener, handler(provider))
+}
+
+#[test]
+fn test_negotiate() {
+    assert_eq!(Format::negotiate(None), Format::Text);
+    assert_eq!(Format::negotiate(Some("*/*")), Format::Text);
+    assert_eq!(Format::negotiate(Some("application/json")), Format::Text);
+    // What Prometheus sends by default
+    assert_eq!(
+        Format::negotiate(Some("application/openmetrics-text;version=1.0.0,application/openmetrics-text;version=0.0.1;q=0.75,text/plain;version=0.0.4;q=0.5,*/*;q=0.1")),
+        Format::OpenMetrics
+    );
+    assert_eq!(
+        Format::negotiate(Some("application/vnd.google.protobuf;proto=io.prometheus.client.MetricFamily;encoding=delimited;q=0.7,text/plain;version=0.0.4;q=0.3")),
+        Format::Protobuf
+    );
+    assert_eq!(
+        Format::negotiate(Some(
+            "application/vnd.google.protobuf;proto=io.prometheus.client.MetricFamily;encoding=text"
+        )),
+        Format::Text
+    );
+    assert_eq!(
+        Format::negotiate(Some("application/openmetrics-text;q=0.2,text/plain;q=0.5")),
+        Format::Text
+    );
+}
+
+#[cfg(test)]
+fn example() -> Vec<Metric> {
+    crate::parse_complete("# TYPE foo counter\nfoo{a=\"b\"} 1\n").unwrap()
+}
+
+#[test]
+fn test_handler() {
+    let h = handler(example);
+    let mut request = Request::new("GET", "/metrics");
+    let res = h(&request);
+    assert_eq!(res.status, 200);
+    assert_eq!(res.header("Content-Type"), Some(TEXT_CONTENT_TYPE));
+    assert_eq!(res.body, render_metrics(&example()).into_bytes());
+    assert_eq!(res.header("Vary"), Some("Accept, Accept-Encoding"));
+    assert_eq!(h(&Request::new("HEAD", "/metrics")), res);
+
+    request.headers.push((
+        "Accept".to_string(),
+        "application/openmetrics-text; version=1.0.0".to_string(),
+    ));
+    request
+        .headers
+        .push(("Accept-Encoding".to_string(), "gzip, br".to_string()));
+    let res = h(&request);
+    assert_eq!(res.header("Content-Type"), Some(OPENMETRICS_CONTENT_TYPE));
+    assert_eq!(res.header("Content-Encoding"), Some("gzip"));
+    let mut body = String::new();
+    io::Read::read_to_string(&mut flate2::read::GzDecoder::new(&res.body[..]), &mut body).unwrap();
+    assert_eq!(body, render_openmetrics(&example()));
+
+    assert_eq!(h(&Request::new("POST", "/metrics")).status, 405);
+    assert_eq!(h(&Request::new("GET", "/")).status, 404);
+}
+
+#[test]
+fn test_serve() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    std::thread::spawn(move || serve(listener, example));
+
+    let mut stream = std::net::TcpStream::connect(addr).unwrap();
+    stream
+        .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    io::Read::read_to_string(&mut stream, &mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(
+        response.ends_with("# TYPE foo counter\nfoo{a=\"b\"} 1\n"),
+        "{}",
+        response
+    );
+
+    // Same headers, without the body
+    let mut stream = std::net::TcpStream::connect(addr).unwrap();
+    stream
+        .write_all(b"HEAD /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut head = String::new();
+    io::Read::read_to_string(&mut stream, &mut head).unwrap();
+    assert!(head.ends_with("\r\n\r\n"), "{}", head);
+    assert_eq!(head, response[..head.len()]);
+}
@@ -0,0 +1,171 @@
+use crate::comment::CommentType;
+use crate::types::{Err, Metric, MetricType, ParseOptions};
+use crate::{LinePosition, LineType};
+use futures_util::stream::{self, Stream};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+struct State<R> {
+    reader: R,
+    options: ParseOptions,
+    buf: Vec<u8>,
+    position: LinePosition,
+    current: Option<Metric>,
+    done: bool,
+}
+
+/// Add a line to the metric being built and return the previous one if this line starts a new metric
+fn push_line(current: &mut Option<Metric>, l: LineType) -> Option<Metric> {
+    match l {
+        LineType::Comment(CommentType::Type(name, t)) => match current {
+            Some(m) if *m.name == *name => {
+                m.append_type_def(t);
+                None
+            }
+            _ => current.replace(Metric::new(&name, t)),
+        },
+        LineType::Comment(CommentType::Help(name, doc)) => match current {
+            Some(m) if *m.name == *name => {
+                m.append_help(doc);
+                None
+            }
+            _ => {
+                let mut m = Metric::new(&name, MetricType::Untyped);
+                m.append_help(doc);
+                current.replace(m)
+            }
+        },
+        LineType::Sample(s) => match current {
+            Some(m) if *m.name == *s.name => {
+                m.push_sample(s.into());
+                None
+            }
+            _ => current.replace(s.into()),
+        },
+        _ => None,
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> State<R> {
+    async fn next_metric(&mut self) -> Option<Result<Metric, Err>> {
+        while !self.done {
+            self.buf.clear();
+            match self.reader.read_until(b'\n', &mut self.buf).await {
+                Ok(0) => self.done = true,
+                Ok(_) => match self.position.parse(&mut self.buf, &self.options) {
+                    Ok(l) => {
+                        if let Some(m) = push_line(&mut self.current, l) {
+                            return Some(Ok(m));
+                        }
+                    }
+                    Result::Err(e) => {
+                        self.done = true;
+                        self.current = None;
+                        return Some(Result::Err(e));
+                    }
+                },
+                Result::Err(e) => {
+                    self.done = true;
+                    self.current = None;
+                    return Some(Result::Err(Err::Io(e)));
+                }
+            }
+        }
+        self.current.take().map(Ok)
+    }
+}
+
+/// Parse an asynchronous reader and return a stream of the metrics extracted from it.
+/// Consecutive lines for the same metric are grouped and the metric is returned as soon as a line for another one is read,
+/// unlike `parse_complete` a metric whose lines aren't contiguous is returned more than once.
+/// The stream ends after the first error.
+pub fn parse_stream<R: AsyncBufRead + Unpin>(
+    reader: R,
+    options: ParseOptions,
+) -> impl Stream<Item = Result<Metric, Err>> {
+    // A line is never buffered further than the body size limit, like in `parse_reader`
+    let limit = options
+        .body_size_limit
+        .map_or(u64::MAX, |l| (l as u64).saturating_add(1));
+    let state = State {
+        reader: reader.take(limit),
+        options,
+        buf: Vec::new(),
+        position: LinePosition::default(),
+        current: None,
+        done: false,
+    };
+    stream::unfold(state, |mut state| async move {
+        state.next_metric().await.map(|m| (m, state))
+    })
+}
+
+#[cfg(test)]
+async fn collect<R: AsyncBufRead + Unpin>(
+    reader: R,
+    options: ParseOptions,
+) -> Vec<Result<Metric, Err>> {
+    use futures_util::StreamExt;
+    parse_stream(reader, options).collect().await
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_parse_stream() {
+    let input = r#"# HELP http_requests_total The total number of HTTP requests.
+# TYPE http_requests_total counter
+http_requests_total{method="post",code="200"} 1027 1395066363000
+http_requests_total{method="post",code="400"} 1028 1395066363000
+
+rpc_duration_seconds_count 2693
+"#;
+    // A tiny buffer makes sure lines spanning several reads are handled
+    let reader = tokio::io::BufReader::with_capacity(3, input.as_bytes());
+    let res: Vec<Metric> = collect(reader, ParseOptions::default())
+        .await
+        .into_iter()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(res, crate::parse_complete(input).unwrap());
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_parse_stream_non_contiguous() {
+    let res = collect(&b"a 1\nb 2\na 3\n"[..], ParseOptions::default()).await;
+    let names: Vec<String> = res
+        .into_iter()
+        .map(|m| m.unwrap().name.to_string())
+        .collect();
+    assert_eq!(names, vec!["a", "b", "a"]);
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_parse_stream_errors() {
+    let res = collect(&b"a 1\nb 2\nc\nd 4\n"[..], ParseOptions::default()).await;
+    assert_eq!(res.len(), 2);
+    assert_eq!(&*res[0].as_ref().unwrap().name, "a");
+    assert!(matches!(res[1], Result::Err(Err::Parse { line: 3, .. })));
+
+    // Missing final line break
+    let res = collect(&b"a 1\nb 2"[..], ParseOptions::default()).await;
+    assert!(res.last().unwrap().is_err());
+    let res = collect(&b"a 1\nb 2"[..], ParseOptions::lenient()).await;
+    assert_eq!(res.len(), 2);
+    assert_eq!(res[1].as_ref().unwrap().samples[0].value, 2f64);
+
+    // An endless line is only read up to the limit
+    let options = ParseOptions {
+        body_size_limit: Some(1000),
+        ..ParseOptions::default()
+    };
+    let reader = tokio::io::BufReader::new(tokio::io::repeat(b'a'));
+    let res = collect(reader, options).await;
+    assert!(matches!(
+        res[..],
+        [Result::Err(Err::LimitExceeded {
+            limit: crate::types::Limit::BodySize(1000),
+            line: 1
+        })]
+    ));
+}
@@ -0,0 +1,99 @@
+//! Attach the labels of the target metrics were scraped from, like `job` and `instance`.
+use crate::labels::Labels;
+use crate::types::{Metric, Sample};
+
+/// Labels added to every sample scraped from a target
+#[derive(Debug, Clone, Default)]
+pub struct TargetLabels {
+    pub labels: Labels,
+    /// Keep the scraped label when it collides with a target label, otherwise the scraped label is renamed to `exported_<name>`
+    pub honor_labels: bool,
+}
+
+impl TargetLabels {
+    pub fn new(labels: &[(&str, &str)], honor_labels: bool) -> Self {
+        TargetLabels {
+            labels: Labels::from_pairs(labels),
+            honor_labels,
+        }
+    }
+
+    /// Add the target labels to a sample, resolving collisions like Prometheus does.
+    /// A label with an empty value is the same as a missing label.
+    pub fn apply_to_sample(&self, sample: &mut Sample) {
+        for (name, value) in &self.labels {
+            if sample.labels.get(name).is_some_and(|v| !v.is_empty()) {
+                if self.honor_labels {
+                    continue;
+                }
+                let scraped = sample.labels.remove(name).unwrap_or_default();
+                let mut exported = format!("exported_{}", name);
+                while sample.labels.contains(&exported) {
+                    exported = format!("exported_{}", exported);
+                }
+                sample.labels.insert(&exported, &scraped);
+            }
+            if value.is_empty() {
+                sample.labels.remove(name);
+            } else {
+                sample.labels.insert(name, value);
+            }
+        }
+    }
+
+    /// Add the target labels to every sample of the metrics
+    pub fn apply(&self, metrics: &mut [Metric]) {
+        for m in metrics {
+            for s in m.samples.iter_mut() {
+                self.apply_to_sample(s);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_apply() {
+    let target = TargetLabels::new(&[("job", "node"), ("instance", "host:9100")], false);
+    let mut sample = Sample::new(1f64, None, vec!["a", "b"]);
+    target.apply_to_sample(&mut sample);
+    assert_eq!(
+        sample,
+        Sample::new(
+            1f64,
+            None,
+            vec!["a", "b", "job", "node", "instance", "host:9100"]
+        )
+    );
+}
+
+#[test]
+fn test_apply_collisions() {
+    let mut metrics =
+        crate::parse_complete("foo{job=\"scraped\",exported_job=\"other\",instance=\"\"} 1\n")
+            .unwrap();
+    TargetLabels::new(&[("job", "node"), ("instance", "host:9100")], false).apply(&mut metrics);
+    assert_eq!(
+        metrics[0].samples[0],
+        Sample::new(
+            1f64,
+            None,
+            vec![
+                "job",
+                "node",
+                "exported_job",
+                "other",
+                "exported_exported_job",
+                "scraped",
+                "instance",
+                "host:9100"
+            ]
+        )
+    );
+
+    let mut metrics = crate::parse_complete("foo{job=\"scraped\"} 1\n").unwrap();
+    TargetLabels::new(&[("job", "node"), ("instance", "host:9100")], true).apply(&mut metrics);
+    assert_eq!(
+        metrics[0].samples[0],
+        Sample::new(1f64, None, vec!["job", "scraped", "instance", "host:9100"])
+    );
+}
@@ -0,0 +1,150 @@
+//! Load metrics from a directory of `*.prom` files like node_exporter's textfile collector.
+use crate::merge::{merge_sources, Conflict, MergeOptions, Source};
+use crate::parse_reader_with_options;
+use crate::types::{Err, Metric, MetricType, ParseOptions, Sample};
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+/// Options for `load_dir`
+#[derive(Debug, Clone, Default)]
+pub struct TextfileOptions {
+    pub parse: ParseOptions,
+    pub merge: MergeOptions,
+}
+
+/// The metrics loaded from a directory, files that failed to be read or parsed are skipped and listed in `errors`
+#[derive(Debug)]
+pub struct Textfiles {
+    /// The merged metrics followed by `node_textfile_mtime_seconds` and `node_textfile_scrape_error`
+    pub metrics: Vec<Metric>,
+    /// The name of each file that couldn't be loaded and why
+    pub errors: Vec<(String, Err)>,
+    pub conflicts: Vec<Conflict>,
+}
+
+fn load_file(path: &Path, options: &ParseOptions) -> Result<(Vec<Metric>, f64), Err> {
+    let mtime = fs::metadata(path)?
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0f64);
+    let metrics = parse_reader_with_options(BufReader::new(File::open(path)?), options)?;
+    Ok((metrics, mtime))
+}
+
+/// Load, parse and merge every `*.prom` file in `dir`, files are merged in the order of their names.
+/// Adds a `node_textfile_mtime_seconds` gauge with the modification time of each loaded file
+/// and a `node_textfile_scrape_error` gauge set to 1 if any file failed to load or conflicted with another one.
+/// Only failing to list the directory is an error.
+pub fn load_dir<P: AsRef<Path>>(dir: P, options: &TextfileOptions) -> Result<Textfiles, Err> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|e| e == "prom") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut sources = Vec::new();
+    let mut errors = Vec::new();
+    let mut mtimes = Metric::new("node_textfile_mtime_seconds", MetricType::Gauge);
+    mtimes.help = Some("Unixtime mtime of textfiles successfully read.".to_string());
+    for path in paths {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        match load_file(&path, &options.parse) {
+            Ok((metrics, mtime)) => {
+                mtimes.push_sample(Sample::new(mtime, None, vec!["file", &name]));
+                sources.push(Source { name, metrics });
+            }
+            Err(e) => errors.push((name, e)),
+        }
+    }
+
+    let merged = merge_sources(sources, &options.merge);
+    let mut error = Metric::new("node_textfile_scrape_error", MetricType::Gauge);
+    error.help = Some("1 if there was an error opening or reading a file, 0 otherwise".to_string());
+    let failed = !errors.is_empty() || !merged.conflicts.is_empty();
+    error.push_sample(Sample::new(if failed { 1f64 } else { 0f64 }, None, vec![]));
+
+    let mut metrics = merged.metrics;
+    if !mtimes.samples.is_empty() {
+        metrics.push(mtimes);
+    }
+    metrics.push(error);
+    Ok(Textfiles {
+        metrics,
+        errors,
+        conflicts: merged.conflicts,
+    })
+}
+
+#[cfg(test)]
+fn test_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("textfile-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn test_load_dir() {
+    let dir = test_dir("load");
+    fs::write(dir.join("b.prom"), "# TYPE foo gauge\nfoo{x=\"2\"} 2\n").unwrap();
+    fs::write(
+        dir.join("a.prom"),
+        "# TYPE foo gauge\nfoo{x=\"1\"} 1\nbar 3\n",
+    )
+    .unwrap();
+    fs::write(dir.join("ignored.txt"), "not metrics").unwrap();
+
+    let res = load_dir(&dir, &TextfileOptions::default()).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(res.errors.is_empty(), "{:?}", res.errors);
+    assert_eq!(res.conflicts, vec![]);
+    let names: Vec<&str> = res.metrics.iter().map(|m| &*m.name).collect();
+    assert_eq!(
+        names,
+        vec![
+            "bar",
+            "foo",
+            "node_textfile_mtime_seconds",
+            "node_textfile_scrape_error"
+        ]
+    );
+    assert_eq!(res.metrics[1].samples.len(), 2);
+    let files: Vec<&str> = res.metrics[2]
+        .samples
+        .iter()
+        .map(|s| &s.labels["file"])
+        .collect();
+    assert_eq!(files, vec!["a.prom", "b.prom"]);
+    assert!(res.metrics[2].samples[0].value > 0f64);
+    assert_eq!(res.metrics[3].samples[0].value, 0f64);
+}
+
+#[test]
+fn test_load_dir_errors() {
+    let dir = test_dir("errors");
+    fs::write(dir.join("ok.prom"), "foo 1\n").unwrap();
+    fs::write(dir.join("broken.prom"), "foo{ 1\n").unwrap();
+
+    let res = load_dir(&dir, &TextfileOptions::default()).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(res.errors.len(), 1);
+    assert_eq!(res.errors[0].0, "broken.prom");
+    let error = res
+        .metrics
+        .iter()
+        .find(|m| &*m.name == "node_textfile_scrape_error")
+        .unwrap();
+    assert_eq!(error.samples[0].value, 1f64);
+    assert!(res.metrics.iter().any(|m| &*m.name == "foo"));
+
+    assert!(load_dir(dir.join("missing"), &TextfileOptions::default()).is_err());
+}
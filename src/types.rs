@@ -1,4 +1,6 @@
-use std::collections::HashMap;
+use crate::labels::Labels;
+use std::fmt;
+use std::sync::Arc;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum MetricType {
@@ -9,52 +11,201 @@ pub enum MetricType {
     Summary,
 }
 
-type NomErr<A> = nom::Err<(A, nom::error::ErrorKind)>;
+impl MetricType {
+    /// The name used for this type in `# TYPE` lines
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MetricType::Untyped => "untyped",
+            MetricType::Counter => "counter",
+            MetricType::Gauge => "gauge",
+            MetricType::Histogram => "histogram",
+            MetricType::Summary => "summary",
+        }
+    }
+}
+
+/// A limit of `ParseOptions`, holding its value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    Samples(usize),
+    Labels(usize),
+    LabelNameLength(usize),
+    LabelValueLength(usize),
+    BodySize(usize),
+    /// Only enforced by decoders buffering lines, like `codec::ExpositionCodec`
+    LineLength(usize),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limit::Samples(l) => write!(f, "sample limit of {}", l),
+            Limit::Labels(l) => write!(f, "label limit of {}", l),
+            Limit::LabelNameLength(l) => write!(f, "label name length limit of {}", l),
+            Limit::LabelValueLength(l) => write!(f, "label value length limit of {}", l),
+            Limit::BodySize(l) => write!(f, "body size limit of {} bytes", l),
+            Limit::LineLength(l) => write!(f, "line length limit of {} bytes", l),
+        }
+    }
+}
 
 #[derive(Debug)]
-pub struct Err(String);
+pub enum Err {
+    /// The input doesn't follow the exposition format at `line`, which is 1-based and quoted in `message`
+    Parse { line: usize, message: String },
+    /// The input isn't valid UTF-8, `line` and `column` are 1-based and point at the first invalid byte
+    InvalidUtf8 {
+        line: usize,
+        column: usize,
+        offset: usize,
+    },
+    /// Reading the input failed
+    Io(std::io::Error),
+    /// Fetching the metrics from a target failed
+    Scrape(String),
+    /// The input exceeds one of the limits of `ParseOptions`, parsing stopped at `line`
+    LimitExceeded { limit: Limit, line: usize },
+}
+
+impl Err {
+    /// Build the error for a line that can't be parsed, `rest` is the input from the start of that line
+    pub(crate) fn parse(line: usize, rest: &str) -> Self {
+        let text = rest.split('\n').next().unwrap_or_default();
+        Err::Parse {
+            line,
+            message: format!("invalid line {:?}", text.trim_end_matches('\r')),
+        }
+    }
+
+    pub(crate) fn invalid_utf8(input: &[u8], e: std::str::Utf8Error) -> Self {
+        let offset = e.valid_up_to();
+        let before = &input[..offset];
+        let line_start = before
+            .iter()
+            .rposition(|&c| c == b'\n')
+            .map_or(0, |i| i + 1);
+        Err::InvalidUtf8 {
+            line: before.iter().filter(|&&c| c == b'\n').count() + 1,
+            column: offset - line_start + 1,
+            offset,
+        }
+    }
+
+    /// Build the error for a single line of a larger input starting at `line_offset`
+    pub(crate) fn invalid_utf8_in_line(
+        line: usize,
+        line_offset: usize,
+        e: std::str::Utf8Error,
+    ) -> Self {
+        Err::InvalidUtf8 {
+            line,
+            column: e.valid_up_to() + 1,
+            offset: line_offset + e.valid_up_to(),
+        }
+    }
+}
 
-impl From<NomErr<&str>> for Err {
-    fn from(t: NomErr<&str>) -> Self {
-        Err(format!("{:?}", t))
+impl fmt::Display for Err {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Err::Parse { line, message } => {
+                write!(f, "parse error at line {}: {}", line, message)
+            }
+            Err::InvalidUtf8 {
+                line,
+                column,
+                offset,
+            } => write!(
+                f,
+                "invalid UTF-8 at line {} column {} (byte {})",
+                line, column, offset
+            ),
+            Err::Io(e) => write!(f, "io error: {}", e),
+            Err::Scrape(s) => write!(f, "scrape error: {}", s),
+            Err::LimitExceeded { limit, line } => write!(f, "{} exceeded at line {}", limit, line),
+        }
     }
 }
 
-#[derive(Debug, PartialEq)]
+impl std::error::Error for Err {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Err::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Err {
+    fn from(e: std::io::Error) -> Self {
+        Err::Io(e)
+    }
+}
+
+/// Options changing how strict the parser is.
+/// The limits mirror the ones of Prometheus' scrape configuration, they're checked line by line
+/// so parsing stops as soon as one is exceeded. There's no limit if they're `None`.
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    /// Accept a last line that isn't terminated by a line break like node_exporter does
+    pub allow_missing_final_newline: bool,
+    /// Maximum number of samples
+    pub sample_limit: Option<usize>,
+    /// Maximum number of labels of a sample
+    pub label_limit: Option<usize>,
+    /// Maximum length in bytes of a label name
+    pub label_name_length_limit: Option<usize>,
+    /// Maximum length in bytes of a label value
+    pub label_value_length_limit: Option<usize>,
+    /// Maximum size in bytes of the input
+    pub body_size_limit: Option<usize>,
+}
+
+impl ParseOptions {
+    /// Options accepting inputs that the reference implementation accepts even if they don't strictly follow the format
+    pub fn lenient() -> Self {
+        ParseOptions {
+            allow_missing_final_newline: true,
+            ..ParseOptions::default()
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub struct Sample {
-    pub labels: HashMap<String, String>,
+    pub labels: Labels,
     pub value: f64,
     pub timestamp: Option<i64>,
 }
 
 impl Sample {
+    /// `labels` alternates names and values, a name without a value is ignored
     pub fn new(value: f64, timestamp: Option<i64>, labels: Vec<&str>) -> Self {
-        let labels = labels
-            .iter()
-            .enumerate()
-            .step_by(2)
-            .map(|(i, _)| (labels[i].to_string(), labels[i + 1].to_string()))
-            .collect();
+        let pairs: Vec<(&str, &str)> = labels.chunks_exact(2).map(|c| (c[0], c[1])).collect();
         Sample {
-            labels,
+            labels: Labels::from_pairs(&pairs),
             value,
             timestamp,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Metric {
-    pub name: String,
+    /// Shared with the metrics of other parses when they're parsed with an `Interner`
+    pub name: Arc<str>,
     pub data_type: MetricType,
+    /// The unescaped docstring from the `# HELP` line
+    pub help: Option<String>,
     pub samples: Vec<Sample>,
 }
 
 impl Metric {
     pub fn new(name: &str, t: MetricType) -> Self {
         Metric {
-            name: name.to_string(),
+            name: Arc::from(name),
             data_type: t,
+            help: None,
             samples: Vec::new(),
         }
     }
@@ -63,3 +214,106 @@ impl Metric {
         self.samples.push(s);
     }
 }
+
+/// A single line of the exposition format
+#[derive(Debug, PartialEq, Clone)]
+pub enum Line {
+    /// A `# HELP` comment, `doc` is unescaped
+    Help {
+        name: String,
+        doc: String,
+    },
+    /// A `# TYPE` comment
+    Type {
+        name: String,
+        data_type: MetricType,
+    },
+    Sample {
+        name: String,
+        sample: Sample,
+    },
+    /// Any other comment, this is the text following the `#`
+    Comment(String),
+    Empty,
+}
+
+/// Weird but valid names, quoted when they aren't legacy names, the suffixes put some metrics in histograms and summaries
+#[cfg(test)]
+const ARBITRARY_NAME: &str =
+    "([a-c_:][a-c0-9_:]{0,3}|[a-c0-9 .é\\\\\"\n]{1,3})(_bucket|_sum|_count)?";
+
+#[cfg(test)]
+impl proptest::arbitrary::Arbitrary for MetricType {
+    type Parameters = ();
+    type Strategy = proptest::strategy::BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        use proptest::prelude::*;
+        prop_oneof![
+            Just(MetricType::Counter),
+            Just(MetricType::Gauge),
+            Just(MetricType::Histogram),
+            Just(MetricType::Summary),
+            Just(MetricType::Untyped),
+        ]
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+impl proptest::arbitrary::Arbitrary for Sample {
+    type Parameters = ();
+    type Strategy = proptest::strategy::BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        use proptest::num::f64::{NEGATIVE, NORMAL, POSITIVE, SUBNORMAL, ZERO};
+        use proptest::prelude::*;
+        // Label names are quoted or match `[a-zA-Z_][a-zA-Z0-9_]*`, unlike metric names they never hold `:`
+        // Label values have characters to escape and some the parsers care about
+        let labels = proptest::collection::vec(
+            (
+                "[a-cA-C_][a-cA-C0-9_]{0,2}|[a-c0-9 .é\\\\\"\n]{1,3}",
+                "[a-c \\\\\"\n\r\té{}=,]{0,4}",
+            ),
+            0..4,
+        );
+        let value = prop_oneof![
+            POSITIVE | NEGATIVE | NORMAL | SUBNORMAL | ZERO,
+            Just(f64::NAN),
+            Just(f64::INFINITY),
+            Just(f64::NEG_INFINITY),
+        ];
+        (labels, value, proptest::option::of(any::<i64>()))
+            .prop_map(|(labels, value, timestamp)| Sample {
+                labels: labels.into_iter().collect(),
+                value,
+                timestamp,
+            })
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+impl proptest::arbitrary::Arbitrary for Metric {
+    type Parameters = ();
+    type Strategy = proptest::strategy::BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        use proptest::prelude::*;
+        // Docstrings can't start with a blank as it's part of the separator, nor hold a carriage return
+        let help = proptest::option::of("([^ \t\r][^\r]{0,5})?");
+        (
+            ARBITRARY_NAME,
+            any::<MetricType>(),
+            help,
+            proptest::collection::vec(any::<Sample>(), 0..4),
+        )
+            .prop_map(|(name, data_type, help, samples)| Metric {
+                name: name.into(),
+                data_type,
+                help,
+                samples,
+            })
+            .boxed()
+    }
+}
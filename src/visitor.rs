@@ -0,0 +1,151 @@
+//! Process an exposition line by line through callbacks instead of building metrics,
+//! for example to sum a counter or find a single series.
+use crate::comment::{unescape_help, CommentType};
+use crate::samples::{RawLabels, SampleEntry};
+use crate::types::{Err, MetricType};
+use crate::{parse_line, LineType};
+use std::borrow::Cow;
+
+/// The callbacks of `parse_with_visitor`, called in the order of the input
+pub trait Visitor {
+    /// A `# HELP` line with its unescaped docstring
+    fn on_help(&mut self, _name: &str, _doc: &str) {}
+
+    /// A `# TYPE` line
+    fn on_type(&mut self, _name: &str, _data_type: MetricType) {}
+
+    /// A sample, its labels are unescaped as they're iterated
+    fn on_sample(&mut self, name: &str, labels: LabelIter, value: f64, timestamp: Option<i64>);
+}
+
+/// The labels of a sample given to `Visitor::on_sample`, in the order of the input.
+/// A name written several times is yielded each time, `Labels` built from them keep the last value.
+/// Names and values are only copied when they contain escape sequences.
+pub struct LabelIter<'a>(<RawLabels<'a> as IntoIterator>::IntoIter);
+
+impl<'a> Iterator for LabelIter<'a> {
+    type Item = (Cow<'a, str>, Cow<'a, str>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+}
+
+fn visit_sample<V: Visitor + ?Sized>(visitor: &mut V, s: &SampleEntry) {
+    visitor.on_sample(
+        &s.name,
+        LabelIter(s.labels.into_iter()),
+        s.value,
+        s.timestamp_ms,
+    );
+}
+
+/// Parse a string calling the visitor for every line, without building any `Metric`.
+/// Labels are never collected, common samples don't allocate at all.
+pub fn parse_with_visitor<V: Visitor + ?Sized>(input: &str, visitor: &mut V) -> Result<(), Err> {
+    let mut rest = input;
+    let mut line = 0;
+    while !rest.is_empty() {
+        line += 1;
+        let (r, l) = parse_line(rest).map_err(|_| Err::parse(line, rest))?;
+        match l {
+            LineType::Comment(CommentType::Help(name, doc)) if doc.contains('\\') => {
+                visitor.on_help(&name, &unescape_help(doc))
+            }
+            LineType::Comment(CommentType::Help(name, doc)) => visitor.on_help(&name, doc),
+            LineType::Comment(CommentType::Type(name, t)) => visitor.on_type(&name, t),
+            LineType::Sample(s) => visit_sample(visitor, &s),
+            LineType::Comment(CommentType::Other(_)) | LineType::Empty => {}
+        }
+        rest = r;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[derive(Default)]
+struct Sum {
+    lines: Vec<String>,
+    total: f64,
+}
+
+#[cfg(test)]
+impl Visitor for Sum {
+    fn on_help(&mut self, name: &str, doc: &str) {
+        self.lines.push(format!("help {} {:?}", name, doc));
+    }
+
+    fn on_type(&mut self, name: &str, data_type: MetricType) {
+        self.lines
+            .push(format!("type {} {}", name, data_type.as_str()));
+    }
+
+    fn on_sample(&mut self, name: &str, labels: LabelIter, value: f64, timestamp: Option<i64>) {
+        let mut labels: Vec<_> = labels.collect();
+        labels.sort();
+        self.lines
+            .push(format!("{} {:?} {} {:?}", name, labels, value, timestamp));
+        if name == "requests_total" {
+            self.total += value;
+        }
+    }
+}
+
+#[test]
+fn test_parse_with_visitor() {
+    let mut sum = Sum::default();
+    parse_with_visitor(
+        r#"# HELP requests_total The requests\nby code
+# TYPE requests_total counter
+requests_total{code="200"} 10 1000
+requests_total{code="500",path="C:\\DIR"} 2
+# A comment
+
+requests_total +Inf
+other NaN
+"#,
+        &mut sum,
+    )
+    .unwrap();
+    assert_eq!(
+        sum.lines,
+        vec![
+            "help requests_total \"The requests\\nby code\"",
+            "type requests_total counter",
+            "requests_total [(\"code\", \"200\")] 10 Some(1000)",
+            "requests_total [(\"code\", \"500\"), (\"path\", \"C:\\\\DIR\")] 2 None",
+            "requests_total [] inf None",
+            "other [] NaN None",
+        ]
+    );
+    assert_eq!(sum.total, f64::INFINITY);
+
+    let mut sum = Sum::default();
+    assert!(matches!(
+        parse_with_visitor("a 1\nb{ 2\n", &mut sum),
+        Result::Err(Err::Parse { line: 2, .. })
+    ));
+    assert_eq!(sum.lines.len(), 1);
+}
+
+#[test]
+fn test_visitor_label_order() {
+    struct Labels(Vec<String>);
+    impl Visitor for Labels {
+        fn on_sample(&mut self, name: &str, labels: LabelIter, _: f64, _: Option<i64>) {
+            let labels: Vec<_> = labels.map(|(k, v)| format!("{}={}", k, v)).collect();
+            self.0.push(format!("{} {}", name, labels.join(",")));
+        }
+    }
+    let mut labels = Labels(Vec::new());
+    // The same labels whether the sample is parsed by hand or with nom
+    parse_with_visitor(
+        "a{b=\"2\",a=\"1\",b=\"3\"} 1\na {b=\"2\",a=\"1\",b=\"3\"} 1\n{\"a.b\",\"c d\"=\"\\n\"} 1\n",
+        &mut labels,
+    )
+    .unwrap();
+    assert_eq!(
+        labels.0,
+        vec!["a b=2,a=1,b=3", "a b=2,a=1,b=3", "a.b c d=\n"]
+    );
+}
@@ -1,13 +1,18 @@
 extern crate prometheus_exposition_format_rs;
 
-use prometheus_exposition_format_rs::parse_complete;
-use prometheus_exposition_format_rs::types::{Err, Metric};
+use prometheus_exposition_format_rs::render::render_metrics;
+use prometheus_exposition_format_rs::types::{Err, Metric, ParseOptions};
+use prometheus_exposition_format_rs::{
+    parse_complete, parse_complete_bytes, parse_complete_bytes_with_options, parse_reader,
+};
+use std::collections::HashSet;
 use std::fs;
+use std::io::BufReader;
 
 const PATH: &str = "fixtures";
 
 fn read_fixture(s: &str) -> Result<Vec<Metric>, Err> {
-    parse_complete(&fs::read_to_string(s).unwrap())
+    parse_complete_bytes(&fs::read(s).unwrap())
 }
 
 fn assert_file_ok(s: &str) -> Vec<Metric> {
@@ -16,29 +21,16 @@ fn assert_file_ok(s: &str) -> Vec<Metric> {
     res.unwrap()
 }
 
-fn assert_file_nok(s: &str) -> Err {
-    let res = read_fixture(s);
-    assert!(
-        res.is_err(),
-        "Succeeded to read file '{}' when we shouldn't got: \n{:?}",
-        s,
-        res
-    );
-    res.unwrap_err()
-}
-
 fn files_with_prefix(prefix: &str) -> Vec<String> {
     // This should look simpler
     // It looks inside the fixture folder and filters files that ends with *.prom and start with a prefix
     fs::read_dir(PATH)
         .unwrap()
-        .into_iter()
         .map(|p| p.unwrap().path())
-        .filter(|p| p.extension().map_or(false, |s| s == "prom"))
+        .filter(|p| p.extension().is_some_and(|s| s == "prom"))
         .filter(|f| {
             f.file_name()
-                .map(|s| s.to_str())
-                .flatten()
+                .and_then(|s| s.to_str())
                 .unwrap()
                 .starts_with(prefix)
         })
@@ -46,16 +38,143 @@ fn files_with_prefix(prefix: &str) -> Vec<String> {
         .collect()
 }
 
+/// The error as written in the manifest
+fn error_kind(e: &Err) -> String {
+    match e {
+        Err::Parse { line, .. } => format!("Parse {}", line),
+        Err::InvalidUtf8 { line, column, .. } => format!("InvalidUtf8 {}:{}", line, column),
+        Err::Io(_) => "Io".to_string(),
+        Err::Scrape(_) => "Scrape".to_string(),
+        Err::LimitExceeded { line, .. } => format!("LimitExceeded {}", line),
+    }
+}
+
 #[test]
-fn test_ok_fixture_files() {
-    for file_name in files_with_prefix("ok_") {
-        assert_file_ok(&file_name);
+fn test_manifest() {
+    let manifest = fs::read_to_string(format!("{}/manifest.txt", PATH)).unwrap();
+    let mut listed = HashSet::new();
+    for line in manifest
+        .lines()
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+    {
+        let parts: Vec<&str> = line.splitn(3, ' ').collect();
+        let (file_name, mode, expected) = (format!("{}/{}", PATH, parts[0]), parts[1], parts[2]);
+        let options = match mode {
+            "strict" => ParseOptions::default(),
+            "lenient" => ParseOptions::lenient(),
+            _ => panic!("Unknown mode in manifest line '{}'", line),
+        };
+        let input = fs::read(&file_name).unwrap();
+        match (
+            expected,
+            parse_complete_bytes_with_options(&input, &options),
+        ) {
+            (result @ "ok", Ok(metrics)) | (result @ "roundtrip", Ok(metrics)) => {
+                // Compared through Debug as NaN values aren't equal to themselves
+                let debug = format!("{:#?}\n", metrics);
+                if result == "ok" {
+                    let golden = format!(
+                        "{}/expected/{}.debug",
+                        PATH,
+                        parts[0].trim_end_matches(".prom")
+                    );
+                    let expected = fs::read_to_string(&golden)
+                        .unwrap_or_else(|e| panic!("Can't read the golden '{}': {}", golden, e));
+                    assert_eq!(
+                        debug, expected,
+                        "Different result when reading '{}' in {} mode",
+                        file_name, mode
+                    );
+                }
+                assert_eq!(
+                    format!(
+                        "{:#?}\n",
+                        parse_complete(&render_metrics(&metrics)).unwrap()
+                    ),
+                    debug,
+                    "Different result after rendering '{}' in {} mode",
+                    file_name,
+                    mode
+                );
+            }
+            (_, Ok(metrics)) => panic!(
+                "Succeeded to read file '{}' in {} mode when we shouldn't got: \n{:?}",
+                file_name, mode, metrics
+            ),
+            (kind, Result::Err(e)) => assert_eq!(
+                error_kind(&e),
+                kind,
+                "Unexpected error when reading '{}' in {} mode: {}",
+                file_name,
+                mode,
+                e
+            ),
+        }
+        listed.insert(file_name);
+    }
+    for file_name in files_with_prefix("") {
+        assert!(
+            listed.contains(&file_name),
+            "'{}' is missing from the manifest",
+            file_name
+        );
     }
 }
 
 #[test]
-fn test_nok_fixture_files() {
+fn test_reader_fixture_files() {
+    for file_name in files_with_prefix("ok_") {
+        let file = BufReader::new(fs::File::open(&file_name).unwrap());
+        // Compared through Debug as NaN values aren't equal to themselves
+        assert_eq!(
+            format!("{:?}", parse_reader(file).unwrap()),
+            format!("{:?}", assert_file_ok(&file_name)),
+            "Different result when reading '{}'",
+            file_name
+        );
+    }
     for file_name in files_with_prefix("nok_") {
-        assert_file_nok(&file_name);
+        let file = BufReader::new(fs::File::open(&file_name).unwrap());
+        assert!(parse_reader(file).is_err(), "file '{}'", file_name);
+    }
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn test_parallel_fixture_files() {
+    use prometheus_exposition_format_rs::parallel::parse_complete_parallel;
+    for file_name in files_with_prefix("ok_")
+        .into_iter()
+        .chain(files_with_prefix("nok_"))
+    {
+        // The parallel parser takes a string so invalid UTF-8 can't reach it
+        let input = match fs::read_to_string(&file_name) {
+            Ok(input) => input,
+            Result::Err(_) => continue,
+        };
+        assert_eq!(
+            format!("{:?}", parse_complete_parallel(&input)),
+            format!("{:?}", read_fixture(&file_name)),
+            "Different result when reading '{}'",
+            file_name
+        );
+    }
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn test_file_fixture_files() {
+    use prometheus_exposition_format_rs::file::parse_file;
+    for file_name in files_with_prefix("ok_")
+        .into_iter()
+        .chain(files_with_prefix("nok_"))
+    {
+        // Safety: the fixtures aren't modified by the tests
+        assert_eq!(
+            format!("{:?}", unsafe { parse_file(&file_name) }),
+            format!("{:?}", read_fixture(&file_name)),
+            "Different result when reading '{}'",
+            file_name
+        );
     }
 }